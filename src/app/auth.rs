@@ -0,0 +1,200 @@
+use crate::app::config::Auth;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use git2::{CertificateCheckStatus, Cred, CredentialType, FetchOptions, RemoteCallbacks};
+use hmac::Mac;
+use std::path::Path;
+
+/// Fetch options carrying the credentials and host key checks of a repository.
+pub fn fetch_options(auth: Option<&Auth>) -> FetchOptions<'static> {
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks(auth));
+    options
+}
+
+pub fn callbacks(auth: Option<&Auth>) -> RemoteCallbacks<'static> {
+    let mut callbacks = RemoteCallbacks::new();
+    let Some(auth) = auth.cloned() else {
+        return callbacks;
+    };
+
+    let known_hosts = auth.known_hosts.clone();
+    // libgit2 asks again after every rejected credential
+    let mut attempts = 0;
+    callbacks.credentials(move |url, username, allowed| {
+        // ssh urls without a user ask for the name first, then for the key
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(auth.username.as_deref().unwrap_or("git"));
+        }
+        attempts += 1;
+        if attempts > 1 {
+            return Err(git2::Error::from_str(&format!(
+                "{} rejected the configured credentials",
+                url
+            )));
+        }
+        credentials(&auth, url, username, allowed)
+    });
+
+    if let Some(known_hosts) = known_hosts {
+        callbacks.certificate_check(move |cert, host| {
+            let Some(hostkey) = cert.as_hostkey() else {
+                // x509 for https, libgit2 checks it against the system store
+                return Ok(CertificateCheckStatus::CertificatePassthrough);
+            };
+            match (hostkey.hostkey_type(), hostkey.hostkey()) {
+                (Some(kind), Some(key)) if known(&known_hosts, host, kind.name(), key)? => {
+                    Ok(CertificateCheckStatus::CertificateOk)
+                }
+                _ => Err(git2::Error::from_str(&format!(
+                    "host key of {} not found in {}",
+                    host,
+                    known_hosts.display()
+                ))),
+            }
+        });
+    }
+
+    callbacks
+}
+
+fn credentials(
+    auth: &Auth,
+    url: &str,
+    username: Option<&str>,
+    allowed: CredentialType,
+) -> Result<Cred, git2::Error> {
+    if allowed.contains(CredentialType::SSH_KEY) {
+        if let Some(key) = &auth.ssh_key {
+            return Cred::ssh_key(
+                username.unwrap_or("git"),
+                None,
+                key,
+                auth.ssh_passphrase.as_deref(),
+            );
+        }
+    }
+    if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+        if let Some(token) = token(auth)? {
+            // forges accept any username next to a token
+            let username = auth
+                .username
+                .as_deref()
+                .or(username)
+                .unwrap_or("x-access-token");
+            return Cred::userpass_plaintext(username, &token);
+        }
+    }
+    Err(git2::Error::from_str(&format!(
+        "no credentials configured for {}",
+        url
+    )))
+}
+
+// read on every fetch so rotated tokens are picked up
+fn token(auth: &Auth) -> Result<Option<String>, git2::Error> {
+    if let Some(file) = &auth.token_path {
+        return std::fs::read_to_string(file)
+            .map(|token| Some(token.trim().to_string()))
+            .map_err(|e| {
+                git2::Error::from_str(&format!("could not read {}: {}", file.display(), e))
+            });
+    }
+    if let Some(var) = &auth.token_env {
+        return std::env::var(var)
+            .map(Some)
+            .map_err(|_| git2::Error::from_str(&format!("{} is not set", var)));
+    }
+    Ok(None)
+}
+
+/// Whether `known_hosts` lists `key` for `host`, hashed host names included.
+fn known(known_hosts: &Path, host: &str, kind: &str, key: &[u8]) -> Result<bool, git2::Error> {
+    let content = std::fs::read_to_string(known_hosts).map_err(|e| {
+        git2::Error::from_str(&format!("could not read {}: {}", known_hosts.display(), e))
+    })?;
+
+    Ok(content.lines().any(|line| {
+        let mut fields = line.split_whitespace();
+        let (Some(hosts), Some(line_kind), Some(line_key)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            return false;
+        };
+        // markers like @revoked or @cert-authority are not plain host keys
+        if hosts.starts_with('@') || hosts.starts_with('#') || line_kind != kind {
+            return false;
+        }
+        STANDARD.decode(line_key).is_ok_and(|k| k == key)
+            && hosts.split(',').any(|pattern| host_matches(pattern, host))
+    }))
+}
+
+fn host_matches(pattern: &str, host: &str) -> bool {
+    if let Some(hashed) = pattern.strip_prefix("|1|") {
+        let Some((salt, hash)) = hashed.split_once('|') else {
+            return false;
+        };
+        let (Ok(salt), Ok(hash)) = (STANDARD.decode(salt), STANDARD.decode(hash)) else {
+            return false;
+        };
+        let Ok(mut mac) = hmac::Hmac::<sha1::Sha1>::new_from_slice(&salt) else {
+            return false;
+        };
+        mac.update(host.as_bytes());
+        return mac.verify_slice(&hash).is_ok();
+    }
+    // `[host]:port` entries, the callback does not tell us the port
+    let name = pattern
+        .strip_prefix('[')
+        .and_then(|p| p.split_once("]:"))
+        .map_or(pattern, |(name, _)| name);
+    name.eq_ignore_ascii_case(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `ssh-keygen -H` of `github.com ssh-ed25519 ...`
+    const HASHED: &str = "|1|v7B2OYTGmYH+AyP64BULA3fjJbU=|RG5gVBek9cAxZtKFD7HRmTKfA90=";
+    const KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+
+    #[test]
+    fn hashed_host() {
+        assert!(host_matches(HASHED, "github.com"));
+        assert!(!host_matches(HASHED, "gitlab.com"));
+        assert!(!host_matches(
+            "|1|v7B2OYTGmYH+AyP64BULA3fjJbU=",
+            "github.com"
+        ));
+        assert!(!host_matches("|1|not base64|RG5g", "github.com"));
+    }
+
+    #[test]
+    fn plain_host() {
+        assert!(host_matches("GitHub.com", "github.com"));
+        assert!(host_matches("[git.example.org]:2222", "git.example.org"));
+        assert!(!host_matches("[git.example.org]:2222", "example.org"));
+    }
+
+    #[test]
+    fn known_hosts_file() {
+        let file = std::env::temp_dir().join(format!("artemis-known-hosts-{}", std::process::id()));
+        std::fs::write(
+            &file,
+            format!(
+                "# comment\n@revoked github.com ssh-ed25519 {key}\n{} ssh-ed25519 {key}\n",
+                HASHED,
+                key = KEY
+            ),
+        )
+        .unwrap();
+        let key = STANDARD.decode(KEY).unwrap();
+
+        assert!(known(&file, "github.com", "ssh-ed25519", &key).unwrap());
+        assert!(!known(&file, "github.com", "ssh-rsa", &key).unwrap());
+        assert!(!known(&file, "gitlab.com", "ssh-ed25519", &key).unwrap());
+        assert!(!known(&file, "github.com", "ssh-ed25519", b"other").unwrap());
+        std::fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,30 @@
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+const DEFAULT_CONFIG: &str = "/etc/artemis/config.yml";
+
+#[derive(Parser, Debug)]
+#[command(name = "artemis", version, about = "Blog and wiki framework")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Clone the content repositories and start the web server
+    Serve {
+        #[arg(short, long, env = "ARTEMIS_CONFIG", default_value = DEFAULT_CONFIG)]
+        config: PathBuf,
+    },
+    /// Load the config and all content, report problems and exit non-zero on failure
+    Check {
+        #[arg(short, long, env = "ARTEMIS_CONFIG", default_value = DEFAULT_CONFIG)]
+        config: PathBuf,
+    },
+    /// Clone or update the content repositories without starting the server
+    Build {
+        #[arg(short, long, env = "ARTEMIS_CONFIG", default_value = DEFAULT_CONFIG)]
+        config: PathBuf,
+    },
+}
@@ -1,34 +1,124 @@
+use crate::app::env;
+use crate::app::signature;
+use crate::app::validate::{self, Problem, Validator};
 use serde::{Deserialize, Serialize};
-use serde_yaml;
-use std::env;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Server {
     pub listen: String,
     pub port: u16,
     pub host: String,
+    /// File the deploy history is kept in, only in memory if unset.
+    pub deploy_log: Option<std::path::PathBuf>,
+    /// Deploys kept per collection.
+    #[serde(default = "default_deploy_log_size")]
+    pub deploy_log_size: usize,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Wiki {
-    pub repository: String,
+fn default_deploy_log_size() -> usize {
+    50
+}
+
+/// How the pages of a collection are presented.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// Chronological list of posts sorted by date.
+    Blog,
+    /// Folder hierarchy of pages.
+    Wiki,
+}
+
+/// What a pull does when the remote history no longer contains the deployed commit.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Diverge {
+    /// Refuse and keep serving the current checkout.
+    #[default]
+    FfOnly,
+    /// Hard reset the branch to the fetched commit.
+    Reset,
+    /// Delete the checkout and clone it again.
+    Reclone,
+}
+
+/// Credentials for private repositories.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct Auth {
+    /// Private key for `ssh://` and scp-like repositories.
+    pub ssh_key: Option<std::path::PathBuf>,
+    pub ssh_passphrase: Option<String>,
+    /// Host keys the ssh server has to present, any key is accepted if unset.
+    pub known_hosts: Option<std::path::PathBuf>,
+    /// Username sent with the token, defaults to the one in the url.
+    pub username: Option<String>,
+    /// File holding the https token, read on every fetch.
+    pub token_path: Option<std::path::PathBuf>,
+    /// Environment variable holding the https token.
+    pub token_env: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Collection {
+    pub name: String,
+    pub kind: Kind,
+    /// Url prefix the collection is served under, e.g. `/notes`.
+    pub prefix: String,
+    /// Url prefix for files below `<path>/images`, defaults to `<prefix>/images`.
+    pub images: Option<String>,
+    /// Git remote to deploy from, `path` is served as is without one.
+    pub repository: Option<String>,
     pub path: std::path::PathBuf,
+    /// Branch to deploy, the remote's default branch if unset.
+    pub branch: Option<String>,
+    #[serde(default)]
+    pub on_diverge: Diverge,
+    pub auth: Option<Auth>,
+    /// Seconds between checks for new commits, for remotes that can't send a webhook.
+    pub poll_interval: Option<u64>,
+    /// Number of commits a fresh clone fetches, the full history if unset.
+    pub depth: Option<u32>,
+    /// Paths to check out, everything if empty.
+    #[serde(default)]
+    pub sparse: Vec<String>,
+    /// Keep a bare repository and serve the deployed commit straight from git.
+    #[serde(default)]
+    pub bare: bool,
+    /// OpenPGP or ssh keys, inline or as files, one of which has to sign every deployed commit.
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Collection {
+    pub fn images(&self) -> String {
+        self.images
+            .clone()
+            .unwrap_or_else(|| format!("{}/images", self.prefix))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Content {
     pub secret: Option<String>,
-    pub wiki: Wiki,
-    pub blog: Blog,
+    #[serde(default)]
+    pub collections: Vec<Collection>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Blog {
-    pub repository: String,
-    pub path: std::path::PathBuf,
+impl Content {
+    /// The webhook secret, none if it is blank. Anyone can sign with an empty key.
+    pub fn secret(&self) -> Option<&str> {
+        self.secret
+            .as_deref()
+            .filter(|secret| !secret.trim().is_empty())
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct IndexPage {
     pub slogan: Option<String>,
     pub title: Option<String>,
@@ -36,49 +126,614 @@ pub struct IndexPage {
     pub links: Option<Vec<String>>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct Contact {
     pub mail: Option<String>,
     pub matrix: Option<String>,
     pub threema: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+/// One website, selected by the `Host` header of a request.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Site {
+    pub host: String,
+    #[serde(default)]
+    pub index: IndexPage,
+    #[serde(default)]
+    pub contact: Contact,
+    pub content: Content,
+}
+
+impl Site {
+    fn matches(&self, host: &str) -> bool {
+        url::Url::parse(&self.host)
+            .ok()
+            .and_then(|url| url.host_str().map(|h| h.eq_ignore_ascii_case(host)))
+            .unwrap_or(false)
+    }
+}
+
+// the top level of the file describes the default site
+#[derive(Deserialize)]
+struct RawConfig {
+    server: Server,
+    content: Content,
+    contact: Contact,
+    index: IndexPage,
+    #[serde(default)]
+    sites: Vec<Site>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(from = "RawConfig")]
 pub struct Config {
     pub server: Server,
-    pub content: Content,
-    pub contact: Contact,
-    pub index: IndexPage,
+    /// Every served site, the default site from the top level comes first.
+    pub sites: Vec<Site>,
 }
 
+impl From<RawConfig> for Config {
+    fn from(raw: RawConfig) -> Config {
+        let mut sites = vec![Site {
+            host: raw.server.host.clone(),
+            index: raw.index,
+            contact: raw.contact,
+            content: raw.content,
+        }];
+        for mut site in raw.sites {
+            // sites without their own webhook secret share the default one
+            if site.content.secret.is_none() {
+                site.content.secret = sites[0].content.secret.clone();
+            }
+            sites.push(site);
+        }
+        Config {
+            server: raw.server,
+            sites,
+        }
+    }
+}
+
+/// Config snapshot shared with the handlers, replaced as a whole on reload.
+#[derive(Clone)]
+pub struct SharedConfig(Arc<RwLock<Arc<Config>>>);
+
+impl SharedConfig {
+    pub fn new(config: Config) -> SharedConfig {
+        SharedConfig(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub fn load(&self) -> Arc<Config> {
+        self.0.read().expect("config lock poisoned").clone()
+    }
+
+    pub fn store(&self, config: Config) {
+        *self.0.write().expect("config lock poisoned") = Arc::new(config);
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::path::PathBuf, std::io::Error),
+    Invalid(std::path::PathBuf, Vec<Problem>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Invalid(path, problems) => {
+                write!(f, "{} problem(s) in {}", problems.len(), path.display())?;
+                for problem in problems {
+                    write!(f, "\n  {}:{}", path.display(), problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 impl Config {
-    pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Config, serde_yaml::Error> {
+    /// Index of the site serving `host`, a hostname without port.
+    pub fn site_for(&self, host: &str) -> Option<usize> {
+        self.sites.iter().position(|site| site.matches(host))
+    }
+
+    pub fn collections(&self) -> impl Iterator<Item = &Collection> {
+        self.sites
+            .iter()
+            .flat_map(|site| site.content.collections.iter())
+    }
+
+    pub fn from_file<P: AsRef<std::path::Path>>(path: P) -> Result<Config, ConfigError> {
         let path = path.as_ref();
-        let config_content = std::fs::File::open(path).expect("Error loading config file");
-        let config_data: Config = serde_yaml::from_reader(config_content)?;
-
-        let server = config_data.server;
-        let mut content = config_data.content;
-        let contact = config_data.contact;
-        let index = config_data.index;
-
-        // grab value from environment if it's not included
-        if content.secret.is_none() {
-            content.secret = match env::var("GITHUB_SECRET") {
-                Ok(val) => Some(val),
-                // panic if there is no secret defined at all
-                Err(_) => {
-                    println!("No GITHUB_SECRET environment variable found.");
-                    None
-                }
-            };
-        };
-
-        Ok(Config {
-            server,
-            content,
-            contact,
-            index,
+        let text =
+            std::fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        // a variable that isn't UTF-8 can't be meant for us
+        let vars = std::env::vars_os().filter_map(|(name, value)| {
+            Some((name.into_string().ok()?, value.into_string().ok()?))
+        });
+        Config::parse(&text, vars)
+            .map_err(|problems| ConfigError::Invalid(path.to_path_buf(), problems))
+    }
+
+    /// Read the config `text` with the environment `vars` on top.
+    fn parse(
+        text: &str,
+        vars: impl Iterator<Item = (String, String)>,
+    ) -> Result<Config, Vec<Problem>> {
+        let mut value: Value =
+            serde_yaml::from_str(text).map_err(|e| vec![Problem::from_yaml(&e)])?;
+
+        // environment variables win over the file
+        let overrides = env::overlay(&mut value, vars);
+        let aliases = fold_legacy(&mut value);
+
+        let mut validator = Validator::new(text, &value, overrides.sources);
+        for (key, alias) in aliases {
+            validator.alias(key, alias);
+        }
+        for (key, error) in overrides.errors {
+            validator.error(&key, error);
+        }
+        validate(&mut validator);
+        let problems = validator.into_problems();
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+
+        // everything was checked above, this only fails if `validate` misses a field
+        validate::deserialize(&value).map_err(|e| {
+            vec![Problem {
+                key: String::new(),
+                line: 1,
+                column: 1,
+                message: e.to_string(),
+            }]
         })
     }
 }
+
+// `content.blog` and `content.wiki` predate collections, they become
+// collections with the url layout they always had.
+fn fold_legacy(value: &mut Value) -> Vec<(String, String)> {
+    let Some(content) = value.get_mut("content").and_then(Value::as_mapping_mut) else {
+        return Vec::new();
+    };
+    if !matches!(
+        content.get("collections"),
+        None | Some(Value::Null) | Some(Value::Sequence(_))
+    ) {
+        return Vec::new();
+    }
+
+    let mut legacy = Vec::new();
+    for (name, images) in [("blog", "/b/images"), ("wiki", "/w/images")] {
+        if let Some(Value::Mapping(mut section)) = content.remove(name) {
+            for (key, default) in [
+                ("name", name.to_string()),
+                ("kind", name.to_string()),
+                ("prefix", format!("/{}", name)),
+                ("images", images.to_string()),
+            ] {
+                section.entry(key.into()).or_insert(Value::String(default));
+            }
+            legacy.push((name, section));
+        }
+    }
+
+    let collections = content
+        .entry("collections".into())
+        .or_insert(Value::Sequence(Vec::new()));
+    if collections.is_null() {
+        *collections = Value::Sequence(Vec::new());
+    }
+    let collections = collections.as_sequence_mut().unwrap();
+
+    let mut aliases = Vec::new();
+    for (name, section) in legacy {
+        aliases.push((
+            format!("content.collections.{}", collections.len()),
+            format!("content.{}", name),
+        ));
+        collections.push(Value::Mapping(section));
+    }
+    aliases
+}
+
+// routes that always win over a collection
+const RESERVED: [&str; 7] = [
+    "/contact",
+    "/healthz",
+    "/update",
+    "/static",
+    "/css",
+    "/pgp-key.txt",
+    "/.well-known",
+];
+
+fn validate_prefix(v: &mut Validator, key: &str, prefix: &str) -> bool {
+    if !prefix.starts_with('/') || prefix.len() < 2 || prefix.ends_with('/') {
+        v.error(key, format!("'{}' must look like /name", prefix));
+        false
+    } else if let Some(route) = RESERVED
+        .iter()
+        .find(|r| prefix == **r || prefix.starts_with(&format!("{}/", r)))
+    {
+        v.error(key, format!("'{}' is taken by {}", prefix, route));
+        false
+    } else {
+        true
+    }
+}
+
+// `names`, `prefixes` and `paths` map what earlier collections claimed to their key
+fn validate_collection(
+    v: &mut Validator,
+    key: &str,
+    names: &mut HashMap<String, String>,
+    prefixes: &mut HashMap<String, String>,
+    paths: &mut HashMap<std::path::PathBuf, String>,
+) {
+    if !v.section(key) {
+        return;
+    }
+    v.known(
+        key,
+        &[
+            "name",
+            "kind",
+            "prefix",
+            "images",
+            "repository",
+            "path",
+            "branch",
+            "on_diverge",
+            "auth",
+            "poll_interval",
+            "depth",
+            "sparse",
+            "bare",
+            "trusted_keys",
+        ],
+    );
+
+    if let Some(name) = v.required::<String>(&format!("{}.name", key)) {
+        if let Some(other) = names.insert(name.clone(), key.to_string()) {
+            v.error(
+                &format!("{}.name", key),
+                format!("'{}' is already used by {}", name, other),
+            );
+        }
+    }
+    v.required::<Kind>(&format!("{}.kind", key));
+
+    let prefix_key = format!("{}.prefix", key);
+    if let Some(prefix) = v.required::<String>(&prefix_key) {
+        if validate_prefix(v, &prefix_key, &prefix) {
+            if let Some(other) = prefixes.insert(prefix.clone(), key.to_string()) {
+                v.error(
+                    &prefix_key,
+                    format!("'{}' is already used by {}", prefix, other),
+                );
+            }
+        }
+    }
+    let images_key = format!("{}.images", key);
+    if let Some(images) = v.optional::<String>(&images_key) {
+        validate_prefix(v, &images_key, &images);
+    }
+
+    let repository_key = format!("{}.repository", key);
+    let local = match v.optional::<String>(&repository_key) {
+        Some(repository) => {
+            v.repository(&repository_key, &repository);
+            false
+        }
+        None => true,
+    };
+    if local {
+        for git in [
+            "branch",
+            "on_diverge",
+            "auth",
+            "poll_interval",
+            "depth",
+            "sparse",
+        ] {
+            let git_key = format!("{}.{}", key, git);
+            if !matches!(v.lookup(&git_key), None | Some(Value::Null)) {
+                v.error(&git_key, "only used with a repository");
+            }
+        }
+    }
+    let branch_key = format!("{}.branch", key);
+    if let Some(branch) = v.optional::<String>(&branch_key) {
+        if !git2::Reference::is_valid_name(&format!("refs/heads/{}", branch)) {
+            v.error(
+                &branch_key,
+                format!("'{}' is not a valid branch name", branch),
+            );
+        }
+    }
+    v.optional::<Diverge>(&format!("{}.on_diverge", key));
+    validate_auth(v, &format!("{}.auth", key));
+    let keys_key = format!("{}.trusted_keys", key);
+    for (i, entry) in v
+        .optional::<Vec<String>>(&keys_key)
+        .unwrap_or_default()
+        .iter()
+        .enumerate()
+    {
+        match signature::parse(entry) {
+            Ok(keys) if keys.is_empty() => v.error(&format!("{}.{}", keys_key, i), "no keys found"),
+            Ok(_) => (),
+            Err(e) => v.error(&format!("{}.{}", keys_key, i), e),
+        }
+    }
+    let poll_key = format!("{}.poll_interval", key);
+    if v.optional::<u64>(&poll_key) == Some(0) {
+        v.error(&poll_key, "interval must be at least one second");
+    }
+    let depth_key = format!("{}.depth", key);
+    if let Some(depth) = v.optional::<u32>(&depth_key) {
+        if depth == 0 || depth > i32::MAX as u32 {
+            v.error(&depth_key, "depth must be between 1 and 2147483647");
+        }
+    }
+    let sparse_key = format!("{}.sparse", key);
+    let bare = v.optional::<bool>(&format!("{}.bare", key)) == Some(true);
+    if let Some(paths) = v.optional::<Vec<String>>(&sparse_key) {
+        if paths.iter().any(|path| path.trim().is_empty()) {
+            v.error(&sparse_key, "paths can't be empty");
+        } else if bare && !paths.is_empty() {
+            v.error(&sparse_key, "a bare repository has no checkout to limit");
+        }
+    }
+    let path_key = format!("{}.path", key);
+    if let Some(path) = v.required::<std::path::PathBuf>(&path_key) {
+        if !local {
+            v.directory(&path_key, &path);
+        } else if !path.is_dir() {
+            v.error(&path_key, format!("{} is not a directory", path.display()));
+        }
+        if let Some(other) = paths.insert(path.clone(), key.to_string()) {
+            v.error(
+                &path_key,
+                format!("{} is already used by {}", path.display(), other),
+            );
+        }
+    }
+}
+
+fn validate_auth(v: &mut Validator, key: &str) {
+    if matches!(v.lookup(key), None | Some(Value::Null)) || !v.section(key) {
+        return;
+    }
+    v.known(
+        key,
+        &[
+            "ssh_key",
+            "ssh_passphrase",
+            "known_hosts",
+            "username",
+            "token_path",
+            "token_env",
+        ],
+    );
+    for file in ["ssh_key", "known_hosts", "token_path"] {
+        let file_key = format!("{}.{}", key, file);
+        if let Some(path) = v.optional::<std::path::PathBuf>(&file_key) {
+            if !path.is_file() {
+                v.error(&file_key, format!("{} does not exist", path.display()));
+            }
+        }
+    }
+    v.optional::<String>(&format!("{}.ssh_passphrase", key));
+    v.optional::<String>(&format!("{}.username", key));
+    let env_key = format!("{}.token_env", key);
+    if let Some(var) = v.optional::<String>(&env_key) {
+        if std::env::var_os(&var).is_none() {
+            v.error(&env_key, format!("{} is not set", var));
+        }
+    }
+}
+
+// `site` is the key of the site, empty for the default site at the top level
+fn validate_site(v: &mut Validator, site: &str, paths: &mut HashMap<std::path::PathBuf, String>) {
+    let key = |name: &str| validate::join(site, name);
+
+    if v.section(&key("content")) {
+        v.known(&key("content"), &["secret", "collections"]);
+        match v.optional::<String>(&key("content.secret")) {
+            None if site.is_empty() => v.error(
+                "content.secret",
+                "no webhook secret, set content.secret or GITHUB_SECRET",
+            ),
+            Some(secret) if secret.trim().is_empty() => {
+                v.error(&key("content.secret"), "webhook secret can't be empty")
+            }
+            _ => (),
+        }
+        match v.lookup(&key("content.collections")) {
+            Some(Value::Sequence(collections)) => {
+                let (mut names, mut prefixes) = (HashMap::new(), HashMap::new());
+                for i in 0..collections.len() {
+                    let collection = key(&format!("content.collections.{}", i));
+                    validate_collection(v, &collection, &mut names, &mut prefixes, paths);
+                }
+            }
+            None | Some(Value::Null) => (),
+            Some(_) => v.error(
+                &key("content.collections"),
+                "expected a list of collections",
+            ),
+        }
+    }
+
+    // only the default site has to spell out contact and index
+    let required = |v: &Validator, name: &str| site.is_empty() || v.lookup(&key(name)).is_some();
+
+    if required(v, "contact") && v.section(&key("contact")) {
+        v.known(&key("contact"), &["mail", "matrix", "threema"]);
+        for field in ["mail", "matrix", "threema"] {
+            v.optional::<String>(&key(&format!("contact.{}", field)));
+        }
+    }
+
+    if required(v, "index") && v.section(&key("index")) {
+        v.known(&key("index"), &["slogan", "title", "skills", "links"]);
+        v.optional::<String>(&key("index.slogan"));
+        v.optional::<String>(&key("index.title"));
+        v.optional::<Vec<String>>(&key("index.skills"));
+        v.optional::<Vec<String>>(&key("index.links"));
+    }
+}
+
+fn validate(v: &mut Validator) {
+    let mut hosts = HashMap::new();
+    v.known("", &["server", "content", "contact", "index", "sites"]);
+    if v.section("server") {
+        v.known(
+            "server",
+            &["listen", "port", "host", "deploy_log", "deploy_log_size"],
+        );
+        if let Some(listen) = v.required::<String>("server.listen") {
+            if listen.parse::<IpAddr>().is_err() {
+                v.error(
+                    "server.listen",
+                    format!("'{}' is not an ip address", listen),
+                );
+            }
+        }
+        if let Some(0) = v.required::<u16>("server.port") {
+            v.error("server.port", "port must be between 1 and 65535");
+        }
+        if let Some(host) = v.required::<String>("server.host") {
+            v.url("server.host", &host);
+            if let Some(name) = host_name(&host) {
+                hosts.insert(name, "server.host".to_string());
+            }
+        }
+        if let Some(log) = v.optional::<std::path::PathBuf>("server.deploy_log") {
+            let missing = log
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty() && !dir.is_dir());
+            if log.is_dir() {
+                v.error(
+                    "server.deploy_log",
+                    format!("{} is a directory", log.display()),
+                );
+            } else if let Some(dir) = missing {
+                v.error(
+                    "server.deploy_log",
+                    format!("{} does not exist", dir.display()),
+                );
+            }
+        }
+        if v.optional::<usize>("server.deploy_log_size") == Some(0) {
+            v.error("server.deploy_log_size", "must keep at least one deploy");
+        }
+    }
+
+    let mut paths = HashMap::new();
+    validate_site(v, "", &mut paths);
+
+    match v.lookup("sites") {
+        Some(Value::Sequence(sites)) => {
+            for i in 0..sites.len() {
+                let site = format!("sites.{}", i);
+                if !v.section(&site) {
+                    continue;
+                }
+                v.known(&site, &["host", "content", "contact", "index"]);
+                let key = format!("{}.host", site);
+                if let Some(host) = v.required::<String>(&key) {
+                    v.url(&key, &host);
+                    let other = host_name(&host).and_then(|name| hosts.insert(name, key.clone()));
+                    if let Some(other) = other {
+                        v.error(&key, format!("'{}' is already served by {}", host, other));
+                    }
+                }
+                validate_site(v, &site, &mut paths);
+            }
+        }
+        None | Some(Value::Null) => (),
+        Some(_) => v.error("sites", "expected a list of sites"),
+    }
+}
+
+fn host_name(host: &str) -> Option<String> {
+    url::Url::parse(host)
+        .ok()?
+        .host_str()
+        .map(str::to_ascii_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BROKEN: &str = "
+server:
+  listen: 0.0.0.0
+  prot: 8080
+  host: https://example.com
+index:
+  title: test
+contact:
+  mail: mail@example.com
+content:
+  secret: secret
+  blog:
+    repository: https://example.com/blog.git
+    path: /tmp/artemis-validate/blog
+    brnch: main
+    depth: 0
+";
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    fn located(problems: &[Problem]) -> Vec<(&str, usize, usize)> {
+        problems
+            .iter()
+            .map(|p| (p.key.as_str(), p.line, p.column))
+            .collect()
+    }
+
+    #[test]
+    fn locates_problems() {
+        let problems = Config::parse(BROKEN, vars(&[("ARTEMIS_SERVER__PORT", "0")])).unwrap_err();
+        assert_eq!(
+            located(&problems),
+            [
+                // not in the file, reported at the closest key that is
+                ("server.port", 2, 1),
+                ("server.prot", 4, 3),
+                // the legacy section keeps its name from the file
+                ("content.blog.brnch", 15, 5),
+                ("content.blog.depth", 16, 5),
+            ]
+        );
+        assert!(problems[0]
+            .message
+            .ends_with("(set by ARTEMIS_SERVER__PORT)"));
+        assert_eq!(problems[1].message, "unknown key, did you mean 'port'?");
+        assert_eq!(problems[2].message, "unknown key, did you mean 'branch'?");
+    }
+
+    #[test]
+    fn syntax_error() {
+        let problems = Config::parse("server:\n  port: [8080\n", vars(&[])).unwrap_err();
+        // the unclosed list is only noticed at the end of the input
+        assert_eq!(located(&problems), [("", 3, 1)]);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::app::config::{Collection, Server};
+use crate::app::git::{self, PageChanges};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// What started a deploy.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trigger {
+    Webhook,
+    Poll,
+    Startup,
+}
+
+/// One deploy of a collection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Deployment {
+    pub trigger: Trigger,
+    /// When the deploy started, RFC 3339.
+    pub started: String,
+    pub old_commit: Option<String>,
+    pub new_commit: Option<String>,
+    pub pages: PageChanges,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+/// The last deploys of every collection, newest first, keyed by checkout path
+/// since that is unique across sites.
+#[derive(Clone)]
+pub struct DeployLog {
+    file: Option<PathBuf>,
+    size: usize,
+    entries: Arc<Mutex<HashMap<PathBuf, VecDeque<Deployment>>>>,
+}
+
+impl DeployLog {
+    /// Pick up the history an earlier run left in `server.deploy_log`.
+    pub fn open(server: &Server) -> DeployLog {
+        let entries = match &server.deploy_log {
+            Some(file) if file.exists() => std::fs::read(file)
+                .map_err(|e| e.to_string())
+                .and_then(|data| serde_json::from_slice(&data).map_err(|e| e.to_string()))
+                .unwrap_or_else(|e| {
+                    println!("Starting a new deploy log, {}: {}", file.display(), e);
+                    HashMap::new()
+                }),
+            _ => HashMap::new(),
+        };
+        DeployLog {
+            file: server.deploy_log.clone(),
+            size: server.deploy_log_size,
+            entries: Arc::new(Mutex::new(entries)),
+        }
+    }
+
+    pub fn record(&self, collection: &Collection, deployment: Deployment) {
+        let mut entries = self.entries.lock().expect("deploy log poisoned");
+        let log = entries.entry(collection.path.clone()).or_default();
+        log.push_front(deployment);
+        log.truncate(self.size);
+
+        let Some(file) = &self.file else {
+            return;
+        };
+        // write aside and rename, a crash never leaves half a log behind
+        let partial = file.with_extension("partial");
+        let written = serde_json::to_vec_pretty(&*entries)
+            .map_err(|e| e.to_string())
+            .and_then(|data| std::fs::write(&partial, data).map_err(|e| e.to_string()))
+            .and_then(|()| std::fs::rename(&partial, file).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            println!("Could not save deploy log {}: {}", file.display(), e);
+        }
+    }
+
+    /// Run the deploy `f` of `collection` and record what it did.
+    pub fn track<T>(
+        &self,
+        collection: &Collection,
+        trigger: Trigger,
+        f: impl FnOnce() -> Result<T, git2::Error>,
+    ) -> Result<T, git2::Error> {
+        let started = chrono::Utc::now();
+        let clock = Instant::now();
+        // none before the first clone
+        let old = git::head_commit(collection).ok();
+        let result = f();
+        let new = git::head_commit(collection).ok();
+
+        let pages = match (&result, new) {
+            (Ok(_), Some(new)) if old != Some(new) => {
+                git::changed_pages(collection, old, new).unwrap_or_default()
+            }
+            _ => PageChanges::default(),
+        };
+        self.record(
+            collection,
+            Deployment {
+                trigger,
+                started: started.to_rfc3339(),
+                old_commit: old.map(|id| id.to_string()),
+                new_commit: new.map(|id| id.to_string()),
+                pages,
+                duration_ms: clock.elapsed().as_millis() as u64,
+                error: result.as_ref().err().map(|e| e.message().to_string()),
+            },
+        );
+        result
+    }
+
+    /// Deploys of `collection`, newest first.
+    pub fn get(&self, collection: &Collection) -> Vec<Deployment> {
+        self.entries
+            .lock()
+            .expect("deploy log poisoned")
+            .get(&collection.path)
+            .map(|log| log.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,281 @@
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+
+const PREFIX: &str = "ARTEMIS_";
+const FILE_SUFFIX: &str = "_file";
+
+/// Result of applying the environment on top of the config file.
+#[derive(Default)]
+pub struct Overrides {
+    /// Dotted config key mapped to the variable that set it.
+    pub sources: HashMap<String, String>,
+    /// Keys whose `_FILE` variable could not be read.
+    pub errors: Vec<(String, String)>,
+}
+
+/// Apply `ARTEMIS_SECTION__KEY=value` variables onto the parsed config.
+///
+/// Segments are separated by `__` and lowercased, numeric segments index into
+/// lists. A `_FILE` suffix reads the value from the named file instead, which
+/// is how mounted secrets are passed in. Values are read as YAML scalars, so
+/// numbers and lists behave the same as in the file.
+pub fn overlay(root: &mut Value, vars: impl Iterator<Item = (String, String)>) -> Overrides {
+    let mut overrides = Overrides::default();
+
+    let mut github_secret = None;
+    let mut vars: Vec<(String, String)> = vars
+        .filter_map(|(name, value)| {
+            if name == "GITHUB_SECRET" {
+                github_secret = Some(value);
+                return None;
+            }
+            (name.starts_with(PREFIX) && name != "ARTEMIS_CONFIG").then_some((name, value))
+        })
+        .collect();
+    vars.sort();
+
+    for (name, raw) in vars {
+        let mut segments: Vec<String> = name[PREFIX.len()..]
+            .split("__")
+            .map(str::to_lowercase)
+            .collect();
+        if segments.iter().any(String::is_empty) {
+            continue;
+        }
+
+        let last = segments.last_mut().unwrap();
+        let raw = match last.strip_suffix(FILE_SUFFIX) {
+            Some(key) if !key.is_empty() => {
+                *last = key.to_string();
+                match std::fs::read_to_string(&raw) {
+                    Ok(content) => content.trim_end_matches(['\r', '\n']).to_string(),
+                    Err(e) => {
+                        overrides
+                            .errors
+                            .push((segments.join("."), format!("could not read {}: {}", raw, e)));
+                        continue;
+                    }
+                }
+            }
+            _ => raw,
+        };
+
+        if set(root, &segments, parse(&raw)) {
+            overrides.sources.insert(segments.join("."), name);
+        } else {
+            overrides.errors.push((
+                segments.join("."),
+                format!("{} indexes past the end of a list", name),
+            ));
+        }
+    }
+
+    // kept for existing deployments that only pass the webhook secret
+    let secret = root.get("content").and_then(|c| c.get("secret"));
+    if secret.is_none_or(Value::is_null) {
+        if let Some(secret) = github_secret {
+            set(
+                root,
+                &["content".to_string(), "secret".to_string()],
+                Value::String(secret),
+            );
+            overrides
+                .sources
+                .insert("content.secret".to_string(), "GITHUB_SECRET".to_string());
+        }
+    }
+
+    overrides
+}
+
+// a mapping is more likely a secret containing ": " than intended structure
+fn parse(raw: &str) -> Value {
+    match serde_yaml::from_str::<Value>(raw) {
+        Ok(Value::Mapping(_)) | Ok(Value::Tagged(_)) | Err(_) => Value::String(raw.to_string()),
+        Ok(value) => value,
+    }
+}
+
+// returns false if a list index does not exist and can not be appended
+fn set(node: &mut Value, segments: &[String], value: Value) -> bool {
+    let Some((segment, rest)) = segments.split_first() else {
+        *node = value;
+        return true;
+    };
+
+    if let Value::Sequence(seq) = node {
+        let Some(i) = segment.parse::<usize>().ok().filter(|i| *i <= seq.len()) else {
+            return false;
+        };
+        if i == seq.len() {
+            seq.push(Value::Null);
+        }
+        return set(&mut seq[i], rest, value);
+    }
+
+    if !node.is_mapping() {
+        *node = Value::Mapping(Mapping::new());
+    }
+    let map = node.as_mapping_mut().unwrap();
+    let key = Value::String(segment.clone());
+    if !map.contains_key(&key) {
+        map.insert(key.clone(), Value::Null);
+    }
+    set(map.get_mut(&key).unwrap(), rest, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "
+server:
+  port: 8080
+content:
+  secret: from-file
+  collections:
+    - name: blog
+      path: /tmp/blog
+";
+
+    fn vars(pairs: &[(&str, &str)]) -> impl Iterator<Item = (String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    fn lookup<'a>(root: &'a Value, key: &str) -> &'a Value {
+        key.split('.').fold(root, |value, segment| match value {
+            Value::Sequence(seq) => &seq[segment.parse::<usize>().unwrap()],
+            _ => &value[segment],
+        })
+    }
+
+    #[test]
+    fn keys_and_list_indexes() {
+        let mut root: Value = serde_yaml::from_str(CONFIG).unwrap();
+        let overrides = overlay(
+            &mut root,
+            vars(&[
+                ("ARTEMIS_SERVER__PORT", "9000"),
+                ("ARTEMIS_CONTENT__COLLECTIONS__0__BRANCH", "published"),
+                ("ARTEMIS_CONTENT__COLLECTIONS__1__NAME", "wiki"),
+                ("ARTEMIS_CONTENT__COLLECTIONS__0__SPARSE", "[posts, images]"),
+                ("ARTEMIS_CONFIG", "/etc/artemis/config.yml"),
+                ("PATH", "/usr/bin"),
+            ]),
+        );
+
+        assert!(overrides.errors.is_empty());
+        assert_eq!(lookup(&root, "server.port"), &Value::from(9000));
+        assert_eq!(
+            lookup(&root, "content.collections.0.branch"),
+            &Value::from("published")
+        );
+        // kept from the file
+        assert_eq!(
+            lookup(&root, "content.collections.0.path"),
+            &Value::from("/tmp/blog")
+        );
+        assert_eq!(
+            lookup(&root, "content.collections.0.sparse"),
+            &serde_yaml::from_str::<Value>("[posts, images]").unwrap()
+        );
+        // the index right after the last entry appends one
+        assert_eq!(
+            lookup(&root, "content.collections.1.name"),
+            &Value::from("wiki")
+        );
+        assert_eq!(
+            overrides.sources.get("content.collections.1.name").unwrap(),
+            "ARTEMIS_CONTENT__COLLECTIONS__1__NAME"
+        );
+        assert_eq!(overrides.sources.len(), 4);
+    }
+
+    #[test]
+    fn past_the_end() {
+        let mut root: Value = serde_yaml::from_str(CONFIG).unwrap();
+        let overrides = overlay(
+            &mut root,
+            vars(&[
+                ("ARTEMIS_CONTENT__COLLECTIONS__5__NAME", "wiki"),
+                ("ARTEMIS_CONTENT__COLLECTIONS__X__NAME", "wiki"),
+                ("ARTEMIS_SERVER____PORT", "1"),
+            ]),
+        );
+
+        let errors: Vec<&str> = overrides
+            .errors
+            .iter()
+            .map(|(key, _)| key.as_str())
+            .collect();
+        assert_eq!(
+            errors,
+            ["content.collections.5.name", "content.collections.x.name"]
+        );
+        assert_eq!(
+            lookup(&root, "content.collections")
+                .as_sequence()
+                .unwrap()
+                .len(),
+            1
+        );
+        assert_eq!(lookup(&root, "server.port"), &Value::from(8080));
+    }
+
+    #[test]
+    fn file_suffix() {
+        let file = std::env::temp_dir().join(format!("artemis-env-secret-{}", std::process::id()));
+        std::fs::write(&file, "key: with colon\n").unwrap();
+        let mut root: Value = serde_yaml::from_str(CONFIG).unwrap();
+        let overrides = overlay(
+            &mut root,
+            vars(&[
+                ("ARTEMIS_CONTENT__SECRET_FILE", file.to_str().unwrap()),
+                ("ARTEMIS_SERVER__HOST_FILE", "/nonexistent/artemis-host"),
+            ]),
+        );
+        std::fs::remove_file(&file).unwrap();
+
+        // read as a string, not the mapping it looks like, without the newline
+        assert_eq!(
+            lookup(&root, "content.secret"),
+            &Value::from("key: with colon")
+        );
+        assert_eq!(
+            overrides.sources.get("content.secret").unwrap(),
+            "ARTEMIS_CONTENT__SECRET_FILE"
+        );
+        assert_eq!(overrides.errors.len(), 1);
+        assert_eq!(overrides.errors[0].0, "server.host");
+        assert!(root["server"].get("host").is_none());
+    }
+
+    #[test]
+    fn github_secret() {
+        // only fills in a secret the file and ARTEMIS_ variables left out
+        let mut root: Value = serde_yaml::from_str(CONFIG).unwrap();
+        overlay(&mut root, vars(&[("GITHUB_SECRET", "from-env")]));
+        assert_eq!(lookup(&root, "content.secret"), &Value::from("from-file"));
+
+        let mut root: Value = serde_yaml::from_str("content:\n  collections: []\n").unwrap();
+        let overrides = overlay(&mut root, vars(&[("GITHUB_SECRET", "from-env")]));
+        assert_eq!(lookup(&root, "content.secret"), &Value::from("from-env"));
+        assert_eq!(
+            overrides.sources.get("content.secret").unwrap(),
+            "GITHUB_SECRET"
+        );
+    }
+
+    #[test]
+    fn scalars() {
+        assert_eq!(parse("42"), Value::from(42));
+        assert_eq!(parse("true"), Value::from(true));
+        assert_eq!(parse("a: b"), Value::from("a: b"));
+        assert_eq!(parse("!tag x"), Value::from("!tag x"));
+        assert_eq!(parse("[unclosed"), Value::from("[unclosed"));
+    }
+}
@@ -0,0 +1,111 @@
+//! Throwaway git remotes and checkouts for tests.
+
+use crate::app::config::{Collection, Diverge, Kind, Server};
+use git2::{IndexAddOption, Oid, Repository, RepositoryInitOptions, Signature};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// A remote repository on branch `main` below a temporary directory, removed on drop.
+pub struct Fixture {
+    pub dir: PathBuf,
+    pub remote: Repository,
+}
+
+impl Fixture {
+    pub fn new() -> Fixture {
+        let dir = std::env::temp_dir().join(format!(
+            "artemis-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut options = RepositoryInitOptions::new();
+        options.initial_head("main");
+        let remote = Repository::init_opts(dir.join("remote"), &options).unwrap();
+        Fixture { dir, remote }
+    }
+
+    /// Write `files` into the remote and commit them on top of `parent`,
+    /// the current HEAD if none. A parent below HEAD rewrites history.
+    pub fn commit_on(&self, parent: Option<Oid>, files: &[(&str, &str)]) -> Oid {
+        let workdir = self.remote.workdir().unwrap();
+        for (name, text) in files {
+            std::fs::write(workdir.join(name), text).unwrap();
+        }
+        let mut index = self.remote.index().unwrap();
+        index.add_all(["*"], IndexAddOption::DEFAULT, None).unwrap();
+        index.write().unwrap();
+        let tree = self.remote.find_tree(index.write_tree().unwrap()).unwrap();
+        let parent = parent.or_else(|| self.remote.refname_to_id("HEAD").ok());
+        let parents: Vec<_> = parent
+            .map(|id| self.remote.find_commit(id).unwrap())
+            .into_iter()
+            .collect();
+        let who = Signature::now("Test", "test@example.org").unwrap();
+        let id = self
+            .remote
+            .commit(
+                None,
+                &who,
+                &who,
+                "test",
+                &tree,
+                &parents.iter().collect::<Vec<_>>(),
+            )
+            .unwrap();
+        self.remote
+            .reference("refs/heads/main", id, true, "test")
+            .unwrap();
+        id
+    }
+
+    pub fn commit(&self, files: &[(&str, &str)]) -> Oid {
+        self.commit_on(None, files)
+    }
+
+    /// A blog collection deploying `main` of the remote into `<dir>/checkout`.
+    pub fn collection(&self, on_diverge: Diverge) -> Collection {
+        Collection {
+            name: "blog".to_string(),
+            kind: Kind::Blog,
+            prefix: "/blog".to_string(),
+            images: None,
+            repository: Some(self.dir.join("remote").to_string_lossy().into_owned()),
+            path: self.dir.join("checkout"),
+            branch: Some("main".to_string()),
+            on_diverge,
+            auth: None,
+            poll_interval: None,
+            depth: None,
+            sparse: Vec::new(),
+            bare: false,
+            trusted_keys: Vec::new(),
+        }
+    }
+
+    pub fn server() -> Server {
+        Server {
+            listen: "127.0.0.1".to_string(),
+            port: 8080,
+            host: "http://localhost".to_string(),
+            deploy_log: None,
+            deploy_log_size: 10,
+        }
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A post `check` accepts.
+pub fn post(title: &str) -> String {
+    format!(
+        "---\ntitle: {}\ndate: M01-02-2024\nauthor: test\n---\n# {}\n",
+        title, title
+    )
+}
@@ -1,33 +1,875 @@
-use git2::Repository;
-use std::fs::create_dir_all;
-use std::path::Path;
+use crate::app::auth;
+use crate::app::config::{Collection, Diverge, Kind};
+use crate::app::signature::{self, TrustedKey};
+use git2::{
+    build::{CheckoutBuilder, RepoBuilder},
+    Direction, Oid, Repository, ResetType,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{create_dir_all, remove_dir_all};
+use std::path::PathBuf;
 
-pub fn clone_repository(repo: &str, path: &Path) -> Result<Repository, git2::Error> {
+/// What a pull did to the checkout.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pull {
+    UpToDate,
+    FastForward,
+    /// History diverged and the branch was hard reset to the remote.
+    Reset,
+    /// History diverged and the checkout was cloned again.
+    Recloned,
+}
+
+impl fmt::Display for Pull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Pull::UpToDate => "up to date",
+            Pull::FastForward => "fast-forward",
+            Pull::Reset => "reset",
+            Pull::Recloned => "reclone",
+        })
+    }
+}
+
+/// Who touched a file and when, taken from the git log.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Authorship {
+    /// Date of the oldest known commit, the start of a shallow history at most.
+    pub created: String,
+    pub modified: String,
+    /// Last commit that changed the file.
+    pub commit: String,
+    /// Commit authors, most recent first.
+    pub contributors: Vec<String>,
+}
+
+impl Authorship {
+    pub fn short_commit(&self) -> &str {
+        self.commit.get(..7).unwrap_or(&self.commit)
+    }
+}
+
+fn date(time: git2::Time) -> String {
+    chrono::DateTime::from_timestamp(time.seconds(), 0)
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+/// Walk the history of the checkout once and collect the authorship of every
+/// file, keyed by its path inside the repository.
+pub fn authorship(collection: &Collection) -> Result<HashMap<PathBuf, Authorship>, git2::Error> {
+    remote(collection)?;
+    let repo = Repository::open(&collection.path)?;
+    let mut walk = repo.revwalk()?;
+    walk.push_head()?;
+    walk.set_sorting(git2::Sort::TIME)?;
+
+    let mut log: HashMap<PathBuf, Authorship> = HashMap::new();
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        // the oldest commit of a shallow clone has no parent and adds every file
+        let parent = commit.parent(0).and_then(|parent| parent.tree()).ok();
+        let diff = repo.diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), None)?;
+
+        let when = date(commit.author().when());
+        let name = commit.author().name().unwrap_or("unknown").to_string();
+        for delta in diff.deltas() {
+            let Some(path) = delta.new_file().path() else {
+                continue;
+            };
+            // newest commits come first
+            let entry = log.entry(path.to_path_buf()).or_insert_with(|| Authorship {
+                modified: when.clone(),
+                commit: commit.id().to_string(),
+                ..Default::default()
+            });
+            entry.created = when.clone();
+            if !entry.contributors.contains(&name) {
+                entry.contributors.push(name.clone());
+            }
+        }
+    }
+    Ok(log)
+}
+
+/// A commit that changed a page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Revision {
+    pub commit: String,
+    /// The revision before this one, none for the first.
+    pub previous: Option<String>,
+    pub date: String,
+    pub author: String,
+    pub summary: String,
+}
+
+impl Revision {
+    pub fn short_commit(&self) -> &str {
+        self.commit.get(..7).unwrap_or(&self.commit)
+    }
+}
+
+// blob of `path` in `commit`, none if the file doesn't exist there
+fn blob_id(commit: &git2::Commit, path: &std::path::Path) -> Option<Oid> {
+    commit
+        .tree()
+        .ok()?
+        .get_path(path)
+        .ok()
+        .map(|entry| entry.id())
+}
+
+/// Commits of the deployed branch that changed `path`, newest first.
+pub fn file_history(
+    collection: &Collection,
+    path: &std::path::Path,
+) -> Result<Vec<Revision>, git2::Error> {
+    remote(collection)?;
+    let repo = Repository::open(&collection.path)?;
+    let mut walk = repo.revwalk()?;
+    walk.push_head()?;
+    walk.set_sorting(git2::Sort::TIME)?;
+
+    let mut revisions = Vec::new();
+    for oid in walk {
+        let commit = repo.find_commit(oid?)?;
+        let Some(blob) = blob_id(&commit, path) else {
+            continue;
+        };
+        // unchanged from the parent, or the start of a shallow history
+        let parent = commit.parent(0).ok();
+        if parent.is_some_and(|parent| blob_id(&parent, path) == Some(blob)) {
+            continue;
+        }
+        revisions.push(Revision {
+            commit: commit.id().to_string(),
+            previous: None,
+            date: date(commit.author().when()),
+            author: commit.author().name().unwrap_or("unknown").to_string(),
+            summary: commit.summary().unwrap_or_default().to_string(),
+        });
+    }
+    for i in 1..revisions.len() {
+        revisions[i - 1].previous = Some(revisions[i].commit.clone());
+    }
+    Ok(revisions)
+}
+
+// text of `path` in the commit `id` points to, none if it doesn't exist there.
+// With `keys` only signed commits are shown, like only signed ones are deployed.
+fn text_at(
+    repo: &Repository,
+    id: &str,
+    path: &std::path::Path,
+    keys: &[TrustedKey],
+) -> Result<Option<String>, git2::Error> {
+    // abbreviated ids are fine, revision syntax from the query string is not
+    let commit = repo.find_commit_by_prefix(id)?;
+    if !keys.is_empty() {
+        signature::verify(repo, commit.id(), keys)?;
+    }
+    let entry = match commit.tree()?.get_path(path) {
+        Ok(entry) => entry,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let blob = entry.to_object(repo)?.peel_to_blob()?;
+    String::from_utf8(blob.content().to_vec())
+        .map(Some)
+        .map_err(|_| git2::Error::from_str("file is not valid UTF-8"))
+}
+
+/// Content of `path` as of `commit`, straight from the object database.
+pub fn file_at(
+    collection: &Collection,
+    commit: &str,
+    path: &std::path::Path,
+) -> Result<String, git2::Error> {
+    remote(collection)?;
+    let repo = Repository::open(&collection.path)?;
+    text_at(&repo, commit, path, &trusted_keys(collection)?)?
+        .ok_or_else(|| git2::Error::from_str("file does not exist in this revision"))
+}
+
+/// Markdown files in the deployed commit, for collections served from a bare
+/// repository. Only the top level unless `recursive`.
+pub fn markdown_files(
+    collection: &Collection,
+    recursive: bool,
+) -> Result<Vec<(PathBuf, Vec<u8>)>, git2::Error> {
+    remote(collection)?;
+    let repo = Repository::open(&collection.path)?;
+    let tree = repo.head()?.peel_to_tree()?;
+
+    let mut files = Vec::new();
+    tree.walk(git2::TreeWalkMode::PreOrder, |dir, entry| {
+        let name = entry.name().unwrap_or_default();
+        match entry.kind() {
+            Some(git2::ObjectType::Tree) if !recursive => git2::TreeWalkResult::Skip,
+            Some(git2::ObjectType::Blob) if name.ends_with(".md") => {
+                if let Ok(blob) = entry.to_object(&repo).and_then(|o| o.peel_to_blob()) {
+                    files.push((PathBuf::from(dir).join(name), blob.content().to_vec()));
+                }
+                git2::TreeWalkResult::Ok
+            }
+            _ => git2::TreeWalkResult::Ok,
+        }
+    })?;
+    Ok(files)
+}
+
+/// Raw content of `path` in the deployed commit.
+pub fn file_bytes(collection: &Collection, path: &std::path::Path) -> Result<Vec<u8>, git2::Error> {
+    remote(collection)?;
+    let repo = Repository::open(&collection.path)?;
+    let entry = repo.head()?.peel_to_tree()?.get_path(path)?;
+    let blob = entry.to_object(&repo)?.peel_to_blob()?;
+    Ok(blob.content().to_vec())
+}
+
+/// A line of a diff, `origin` is `+`, `-`, ` ` or `@` for a hunk header.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffLine {
+    pub origin: char,
+    pub content: String,
+}
+
+impl DiffLine {
+    /// Css class of the line.
+    pub fn class(&self) -> &'static str {
+        match self.origin {
+            '+' => "added",
+            '-' => "removed",
+            '@' => "hunk",
+            _ => "context",
+        }
+    }
+}
+
+/// Line diff of `path` between two commits, a missing file counts as empty.
+pub fn file_diff(
+    collection: &Collection,
+    old: &str,
+    new: &str,
+    path: &std::path::Path,
+) -> Result<Vec<DiffLine>, git2::Error> {
+    remote(collection)?;
+    let repo = Repository::open(&collection.path)?;
+    let keys = trusted_keys(collection)?;
+    let old = text_at(&repo, old, path, &keys)?;
+    let new = text_at(&repo, new, path, &keys)?;
+    if old.is_none() && new.is_none() {
+        return Err(git2::Error::from_str("file exists in neither revision"));
+    }
+    let (old, new) = (old.unwrap_or_default(), new.unwrap_or_default());
+
+    let patch =
+        git2::Patch::from_buffers(old.as_bytes(), Some(path), new.as_bytes(), Some(path), None)?;
+    let mut lines = Vec::new();
+    for hunk in 0..patch.num_hunks() {
+        let (header, count) = patch.hunk(hunk)?;
+        lines.push(DiffLine {
+            origin: '@',
+            content: String::from_utf8_lossy(header.header())
+                .trim_end()
+                .to_string(),
+        });
+        for line in 0..count {
+            let line = patch.line_in_hunk(hunk, line)?;
+            lines.push(DiffLine {
+                origin: line.origin(),
+                content: String::from_utf8_lossy(line.content())
+                    .trim_end_matches('\n')
+                    .to_string(),
+            });
+        }
+    }
+    Ok(lines)
+}
+
+/// State of a content checkout after `prepare_checkout`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Checkout {
+    Cloned,
+    /// The checkout matches the config.
+    Verified,
+    /// The checkout was fixed in place, the reason says what was wrong.
+    Repaired(String),
+    /// The checkout was unusable and cloned again.
+    Recloned(String),
+}
+
+// the remote to deploy from, local collections have nothing to clone or pull
+fn remote(collection: &Collection) -> Result<&str, git2::Error> {
+    collection.repository.as_deref().ok_or_else(|| {
+        git2::Error::from_str(&format!(
+            "{}: {} is a local directory without a repository",
+            collection.name,
+            collection.path.display()
+        ))
+    })
+}
+
+// compare remotes the way people write them, with or without `.git` and slashes
+fn same_remote(a: &str, b: &str) -> bool {
+    let normalize = |url: &str| {
+        let url = url.strip_prefix("file://").unwrap_or(url);
+        let url = url.trim_end_matches('/');
+        let url = url.strip_suffix(".git").unwrap_or(url).to_string();
+        std::fs::canonicalize(&url)
+            .map(|p| p.to_string_lossy().into_owned())
+            .unwrap_or(url)
+    };
+    normalize(a) == normalize(b)
+}
+
+/// Clone the collection, or make sure an existing checkout is the configured
+/// repository and branch.
+pub fn prepare_checkout(collection: &Collection) -> Result<Checkout, git2::Error> {
+    let repository = remote(collection)?;
+    let path = &collection.path;
+    let empty = std::fs::read_dir(path).map_or(true, |mut dir| dir.next().is_none());
+    if empty {
+        clone_repository(collection)?;
+        return Ok(Checkout::Cloned);
+    }
+
+    let repo = match Repository::open(path) {
+        Ok(repo) => repo,
+        // someone else's files, don't touch them
+        Err(_) if !path.join(".git").exists() && !path.join("HEAD").exists() => {
+            return Err(git2::Error::from_str(&format!(
+                "{}: {} is not empty and not a git checkout",
+                collection.name,
+                path.display()
+            )))
+        }
+        Err(e) => return reclone(collection, format!("broken checkout: {}", e.message())),
+    };
+
+    if repo.is_bare() != collection.bare {
+        drop(repo);
+        let reason = match collection.bare {
+            true => "a bare repository is configured",
+            false => "a checkout is configured instead of a bare repository",
+        };
+        return reclone(collection, reason.to_string());
+    }
+
+    let origin = repo
+        .find_remote("origin")
+        .ok()
+        .and_then(|remote| remote.url().map(str::to_string));
+    let mut repaired = Vec::new();
+    match origin {
+        Some(url) if same_remote(&url, repository) => (),
+        Some(url) => {
+            return Err(git2::Error::from_str(&format!(
+                "{}: {} is a clone of {}, not {}. Remove it or fix the path",
+                collection.name,
+                path.display(),
+                url,
+                repository
+            )))
+        }
+        None => {
+            let _ = repo.remote_delete("origin");
+            repo.remote("origin", repository)?;
+            repaired.push("origin was missing".to_string());
+        }
+    }
+
+    // an interrupted clone has no commit to serve
+    let head = repo.head().and_then(|head| head.peel_to_commit()).err();
+    if let Some(e) = head {
+        drop(repo);
+        return reclone(collection, format!("no usable HEAD: {}", e.message()));
+    }
+
+    let on_branch = match &collection.branch {
+        Some(branch) => {
+            let head = repo.head()?;
+            head.is_branch() && head.shorthand() == Some(branch.as_str())
+        }
+        None => true,
+    };
+    if !on_branch {
+        repaired.push(format!(
+            "switched to {}",
+            collection.branch.as_deref().unwrap_or_default()
+        ));
+    }
+
+    let mut options = git2::StatusOptions::new();
+    options.include_untracked(false);
+    // files outside a sparse checkout are missing on purpose
+    for path in &collection.sparse {
+        options.pathspec(path);
+    }
+    if !repo.is_bare() && !repo.statuses(Some(&mut options))?.is_empty() {
+        repo.checkout_head(Some(&mut checkout(collection)))?;
+        repaired.push("discarded local changes".to_string());
+    }
+    drop(repo);
+
+    // pulling switches the branch
+    if !on_branch {
+        pull_repository(collection, |_| Ok(()))?;
+    }
+
+    // the keys may be newer than the deployed commit, don't keep serving it unchecked
+    if !collection.trusted_keys.is_empty() {
+        let repo = Repository::open(path)?;
+        let head = repo.head()?.peel_to_commit()?.id();
+        signature::verify(&repo, head, &trusted_keys(collection)?)
+            .map_err(|e| git2::Error::from_str(&format!("{}: {}", collection.name, e.message())))?;
+    }
+
+    if repaired.is_empty() {
+        Ok(Checkout::Verified)
+    } else {
+        Ok(Checkout::Repaired(repaired.join(", ")))
+    }
+}
+
+fn reclone(collection: &Collection, reason: String) -> Result<Checkout, git2::Error> {
+    clone_aside(collection, |_| Ok(()))?;
+    Ok(Checkout::Recloned(reason))
+}
+
+/// Clone the collection next to its checkout and swap it in once the clone
+/// succeeded and `accept` is happy with it, otherwise the old checkout keeps serving.
+fn clone_aside(
+    collection: &Collection,
+    accept: impl FnOnce(&Collection) -> Result<(), String>,
+) -> Result<(), git2::Error> {
+    let io = |e: std::io::Error| git2::Error::from_str(&e.to_string());
+    let sibling = |suffix: &str| {
+        let mut name = collection.path.file_name().unwrap_or_default().to_owned();
+        name.push(suffix);
+        collection.path.with_file_name(name)
+    };
+    let (fresh, old) = (sibling(".reclone"), sibling(".old"));
+    // leftovers of a run that died halfway
+    for leftover in [&fresh, &old] {
+        if leftover.exists() {
+            remove_dir_all(leftover).map_err(io)?;
+        }
+    }
+
+    let mut clone = collection.clone();
+    clone.path = fresh.clone();
+    if let Err(e) = clone_repository(&clone) {
+        let _ = remove_dir_all(&fresh);
+        return Err(e);
+    }
+    if let Err(why) = accept(&clone) {
+        let _ = remove_dir_all(&fresh);
+        return Err(git2::Error::from_str(&why));
+    }
+
+    std::fs::rename(&collection.path, &old).map_err(io)?;
+    if let Err(e) = std::fs::rename(&fresh, &collection.path) {
+        let _ = std::fs::rename(&old, &collection.path);
+        let _ = remove_dir_all(&fresh);
+        return Err(io(e));
+    }
+    if let Err(e) = remove_dir_all(&old) {
+        println!("Could not remove {}: {}", old.display(), e);
+    }
+    Ok(())
+}
+
+pub fn clone_repository(collection: &Collection) -> Result<Repository, git2::Error> {
+    let repository = remote(collection)?;
+    let path = &collection.path;
     create_dir_all(path).expect("Failed to create directory for post files");
-    println!("Cloning {} to {}", repo, path.to_str().unwrap());
-    Repository::clone(repo, path)
+    println!("Cloning {} to {}", repository, path.to_str().unwrap());
+    let mut builder = RepoBuilder::new();
+    let mut options = auth::fetch_options(collection.auth.as_ref());
+    if let Some(depth) = collection.depth {
+        // libgit2 can't do this over the local transport and fetches everything
+        options.depth(depth as i32);
+    }
+    builder.fetch_options(options);
+    builder.bare(collection.bare);
+    builder.with_checkout(checkout(collection));
+    // without a branch the remote's default branch is checked out
+    if let Some(branch) = &collection.branch {
+        builder.branch(branch);
+    }
+    let repo = builder.clone(repository, path)?;
+
+    if !collection.trusted_keys.is_empty() {
+        let head = repo.head()?.peel_to_commit()?.id();
+        if let Err(e) = signature::verify(&repo, head, &trusted_keys(collection)?) {
+            // never leave untrusted content behind to be served
+            drop(repo);
+            let _ = remove_dir_all(path);
+            return Err(e);
+        }
+    }
+    Ok(repo)
+}
+
+// forced checkout limited to the sparse paths, if any
+fn checkout(collection: &Collection) -> CheckoutBuilder<'static> {
+    let mut builder = CheckoutBuilder::new();
+    builder.force();
+    for path in &collection.sparse {
+        builder.path(path.as_str());
+    }
+    builder
+}
+
+// keys the deployed commits have to be signed with, none if anything goes
+fn trusted_keys(collection: &Collection) -> Result<Vec<TrustedKey>, git2::Error> {
+    let mut keys = Vec::new();
+    for entry in &collection.trusted_keys {
+        keys.extend(signature::parse(entry).map_err(|e| git2::Error::from_str(&e))?);
+    }
+    Ok(keys)
+}
+
+// bare repositories are read from the object database and have nothing to check out
+fn update_worktree(repo: &Repository, collection: &Collection) -> Result<(), git2::Error> {
+    if repo.is_bare() {
+        return Ok(());
+    }
+    repo.checkout_head(Some(&mut checkout(collection)))
+}
+
+// the configured branch, or whatever the checkout is on
+fn branch(repo: &Repository, collection: &Collection) -> Result<String, git2::Error> {
+    if let Some(branch) = &collection.branch {
+        return Ok(branch.clone());
+    }
+    let head = repo.head()?;
+    match head.shorthand() {
+        Some(branch) if head.is_branch() => Ok(branch.to_string()),
+        _ => Err(git2::Error::from_str(
+            "HEAD is detached, configure a branch to deploy",
+        )),
+    }
+}
+
+/// Commit the collection's checkout is at.
+pub fn head_commit(collection: &Collection) -> Result<Oid, git2::Error> {
+    let repo = Repository::open(&collection.path)?;
+    let commit = repo.head()?.peel_to_commit()?;
+    Ok(commit.id())
+}
+
+/// Pages a deploy touched, counted by file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct PageChanges {
+    pub added: usize,
+    pub changed: usize,
+    pub removed: usize,
+}
+
+/// Markdown files the collection serves that differ between `old` and `new`,
+/// every page counts as added without an `old` commit.
+pub fn changed_pages(
+    collection: &Collection,
+    old: Option<Oid>,
+    new: Oid,
+) -> Result<PageChanges, git2::Error> {
+    let repo = Repository::open(&collection.path)?;
+    let old = old.map(|old| repo.find_commit(old)?.tree()).transpose()?;
+    let new = repo.find_commit(new)?.tree()?;
+    let diff = repo.diff_tree_to_tree(old.as_ref(), Some(&new), None)?;
+    let sparse = match collection.sparse.is_empty() {
+        true => None,
+        false => Some(git2::Pathspec::new(&collection.sparse)?),
+    };
+
+    let mut changes = PageChanges::default();
+    for delta in diff.deltas() {
+        let Some(path) = delta.new_file().path().or(delta.old_file().path()) else {
+            continue;
+        };
+        // only what `Pages::load` would pick up
+        let nested = path
+            .parent()
+            .is_some_and(|dir| dir != std::path::Path::new(""));
+        if path.extension().is_none_or(|ext| ext != "md")
+            || (nested && collection.kind == Kind::Blog)
+            || sparse
+                .as_ref()
+                .is_some_and(|sparse| !sparse.matches_path(path, git2::PathspecFlags::DEFAULT))
+        {
+            continue;
+        }
+        match delta.status() {
+            git2::Delta::Added => changes.added += 1,
+            git2::Delta::Deleted => changes.removed += 1,
+            _ => changes.changed += 1,
+        }
+    }
+    Ok(changes)
+}
+
+/// Branch the collection deploys, the configured one or whatever the checkout has.
+pub fn deployed_branch(collection: &Collection) -> Result<String, git2::Error> {
+    remote(collection)?;
+    let repo = Repository::open(&collection.path)?;
+    branch(&repo, collection)
+}
+
+/// Whether a push to the repository `full_name` (`owner/repo`), reachable at
+/// any of `urls`, concerns the collection.
+pub fn is_repository(collection: &Collection, full_name: &str, urls: &[&str]) -> bool {
+    let Ok(repository) = remote(collection) else {
+        return false;
+    };
+    if urls.iter().any(|url| same_remote(repository, url)) {
+        return true;
+    }
+
+    // https://host/owner/repo.git and git@host:owner/repo alike
+    let repository = repository.trim_end_matches('/').to_ascii_lowercase();
+    let repository = repository.strip_suffix(".git").unwrap_or(&repository);
+    let full_name = full_name.trim_matches('/').to_ascii_lowercase();
+    !full_name.is_empty()
+        && (repository.ends_with(&format!("/{}", full_name))
+            || repository.ends_with(&format!(":{}", full_name)))
+}
+
+/// Commits the remote and the checkout have for the deployed branch, without fetching.
+pub fn heads(collection: &Collection) -> Result<(Oid, Option<Oid>), git2::Error> {
+    remote(collection)?;
+    let repo = Repository::open(&collection.path)?;
+    let branch = branch(&repo, collection)?;
+    let refname = format!("refs/heads/{}", branch);
+
+    let mut remote = repo.find_remote("origin")?;
+    let connection = remote.connect_auth(
+        Direction::Fetch,
+        Some(auth::callbacks(collection.auth.as_ref())),
+        None,
+    )?;
+    let remote = connection
+        .list()?
+        .iter()
+        .find(|head| head.name() == refname)
+        .map(|head| head.oid())
+        .ok_or_else(|| git2::Error::from_str(&format!("remote has no branch {}", branch)))?;
+
+    Ok((remote, repo.refname_to_id(&refname).ok()))
+}
+
+/// Move the deployed branch back to `commit` after its new content was rejected.
+pub fn rollback(collection: &Collection, commit: Oid) -> Result<(), git2::Error> {
+    let repo = Repository::open(&collection.path)?;
+    let refname = format!("refs/heads/{}", branch(&repo, collection)?);
+    if repo.is_bare() {
+        repo.reference(&refname, commit, true, "Rollback")?;
+    } else {
+        let target = repo.find_object(commit, None)?;
+        repo.reset(&target, ResetType::Hard, Some(&mut checkout(collection)))?;
+    }
+    Ok(())
 }
 
-pub fn pull_repository(path: &Path) -> Result<(), git2::Error> {
-    let repo = Repository::open(path)?;
+/// Fetch and move the deployed branch to the remote. `accept` only sees a
+/// reclone, which can't be rolled back once the old checkout is gone.
+pub fn pull_repository(
+    collection: &Collection,
+    accept: impl FnOnce(&Collection) -> Result<(), String>,
+) -> Result<Pull, git2::Error> {
+    let repository = remote(collection)?;
+    let repo = Repository::open(&collection.path)?;
+    let branch = branch(&repo, collection)?;
 
-    repo.find_remote("origin")?
-        .fetch(&["main".to_string()], None, None)?;
+    repo.find_remote("origin")?.fetch(
+        &[&branch],
+        Some(&mut auth::fetch_options(collection.auth.as_ref())),
+        None,
+    )?;
 
     let fetch_head = repo.find_reference("FETCH_HEAD")?;
     let fetch_commit = repo.reference_to_annotated_commit(&fetch_head)?;
+    let refname = format!("refs/heads/{}", branch);
 
-    let analysis = repo.merge_analysis(&[&fetch_commit])?;
-    if analysis.0.is_up_to_date() {
-        Ok(())
-    } else if analysis.0.is_fast_forward() {
-        // TODO add functionality to pull from a different branch
-        let refname = "refs/heads/main".to_string();
+    // check before anything moves, a rejected commit leaves the checkout as is
+    if !collection.trusted_keys.is_empty()
+        && repo.refname_to_id(&refname).ok() != Some(fetch_commit.id())
+    {
+        let keys = trusted_keys(collection)?;
+        if let Err(e) = signature::verify(&repo, fetch_commit.id(), &keys) {
+            println!("{}: {}", collection.name, e.message());
+            return Err(e);
+        }
+    }
+
+    // the branch changed since the clone, start it at the fetched commit
+    if repo.find_reference(&refname).is_err() {
+        repo.reference(&refname, fetch_commit.id(), false, "Branch")?;
+    }
+    if repo.head()?.name() != Some(refname.as_str()) {
+        repo.set_head(&refname)?;
+        update_worktree(&repo, collection)?;
+    }
+
+    // merge analysis wants a working tree, bare repositories compare the graph
+    let deployed = repo.refname_to_id(&refname)?;
+    let (up_to_date, fast_forward) = if repo.is_bare() {
+        (
+            deployed == fetch_commit.id()
+                || repo.graph_descendant_of(deployed, fetch_commit.id())?,
+            repo.graph_descendant_of(fetch_commit.id(), deployed)?,
+        )
+    } else {
+        let analysis = repo.merge_analysis(&[&fetch_commit])?;
+        (analysis.0.is_up_to_date(), analysis.0.is_fast_forward())
+    };
+    if up_to_date {
+        return Ok(Pull::UpToDate);
+    } else if fast_forward {
         let mut reference = repo.find_reference(&refname)?;
         reference.set_target(fetch_commit.id(), "Fast-Forward")?;
         repo.set_head(&refname)?;
-        repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
-    } else {
-        Err(git2::Error::from_str("Fast-forward only!"))
+        update_worktree(&repo, collection)?;
+        return Ok(Pull::FastForward);
+    }
+
+    // the remote was rebased or force-pushed
+    match collection.on_diverge {
+        Diverge::FfOnly => Err(git2::Error::from_str("Fast-forward only!")),
+        Diverge::Reset => {
+            println!(
+                "{}: history diverged, resetting {} to {}",
+                collection.name,
+                branch,
+                fetch_commit.id()
+            );
+            if repo.is_bare() {
+                repo.find_reference(&refname)?
+                    .set_target(fetch_commit.id(), "Reset")?;
+            } else {
+                let target = repo.find_object(fetch_commit.id(), None)?;
+                repo.reset(&target, ResetType::Hard, Some(&mut checkout(collection)))?;
+            }
+            Ok(Pull::Reset)
+        }
+        Diverge::Reclone => {
+            println!(
+                "{}: history diverged, cloning {} again",
+                collection.name, repository
+            );
+            // release the old checkout before moving it away
+            drop(fetch_commit);
+            drop(fetch_head);
+            drop(repo);
+            clone_aside(collection, |fresh| {
+                accept(fresh).map_err(|why| {
+                    format!("rejected new content, still serving {}: {}", deployed, why)
+                })
+            })?;
+            Ok(Pull::Recloned)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::fixture::{post, Fixture};
+
+    // a checkout of two commits whose remote was then force-pushed to another second commit
+    fn diverged(on_diverge: Diverge) -> (Fixture, Collection, Oid, Oid) {
+        let fixture = Fixture::new();
+        let base = fixture.commit(&[("first.md", &post("first"))]);
+        let old = fixture.commit(&[("second.md", &post("second"))]);
+        let collection = fixture.collection(on_diverge);
+        assert_eq!(prepare_checkout(&collection).unwrap(), Checkout::Cloned);
+        let new = fixture.commit_on(Some(base), &[("second.md", &post("rewritten"))]);
+        (fixture, collection, old, new)
+    }
+
+    #[test]
+    fn fast_forward() {
+        let fixture = Fixture::new();
+        fixture.commit(&[("first.md", &post("first"))]);
+        let collection = fixture.collection(Diverge::FfOnly);
+        prepare_checkout(&collection).unwrap();
+        assert_eq!(
+            pull_repository(&collection, |_| Ok(())).unwrap(),
+            Pull::UpToDate
+        );
+
+        let new = fixture.commit(&[("second.md", &post("second"))]);
+        let pull = pull_repository(&collection, |_| Ok(())).unwrap();
+        assert_eq!(pull, Pull::FastForward);
+        assert_eq!(head_commit(&collection).unwrap(), new);
+        assert!(collection.path.join("second.md").exists());
+    }
+
+    #[test]
+    fn diverged_ff_only() {
+        let (_fixture, collection, old, _) = diverged(Diverge::FfOnly);
+        assert!(pull_repository(&collection, |_| Ok(())).is_err());
+        assert_eq!(head_commit(&collection).unwrap(), old);
+    }
+
+    #[test]
+    fn diverged_reset() {
+        let (_fixture, collection, _, new) = diverged(Diverge::Reset);
+        assert_eq!(
+            pull_repository(&collection, |_| Ok(())).unwrap(),
+            Pull::Reset
+        );
+        assert_eq!(head_commit(&collection).unwrap(), new);
+        let text = std::fs::read_to_string(collection.path.join("second.md")).unwrap();
+        assert!(text.contains("rewritten"));
+    }
+
+    #[test]
+    fn diverged_reclone() {
+        let (fixture, collection, _, new) = diverged(Diverge::Reclone);
+        let pull = pull_repository(&collection, |_| Ok(())).unwrap();
+        assert_eq!(pull, Pull::Recloned);
+        assert_eq!(head_commit(&collection).unwrap(), new);
+        // nothing left next to the checkout
+        assert!(!fixture.dir.join("checkout.reclone").exists());
+        assert!(!fixture.dir.join("checkout.old").exists());
+    }
+
+    #[test]
+    fn rejected_reclone_keeps_checkout() {
+        let (fixture, collection, old, _) = diverged(Diverge::Reclone);
+        let e = pull_repository(&collection, |fresh| {
+            // the check sees the new clone, not the served checkout
+            assert_eq!(fresh.path, fixture.dir.join("checkout.reclone"));
+            Err("no".to_string())
+        })
+        .unwrap_err();
+        assert!(e.message().ends_with(": no"));
+        assert_eq!(head_commit(&collection).unwrap(), old);
+        assert!(!fixture.dir.join("checkout.reclone").exists());
+    }
+
+    #[test]
+    fn failed_reclone_keeps_checkout() {
+        let fixture = Fixture::new();
+        let old = fixture.commit(&[("first.md", &post("first"))]);
+        let mut collection = fixture.collection(Diverge::Reclone);
+        prepare_checkout(&collection).unwrap();
+
+        // the remote is gone, the clone fails before anything is swapped
+        collection.repository = Some(fixture.dir.join("gone").to_string_lossy().into_owned());
+        assert!(clone_aside(&collection, |_| Ok(())).is_err());
+        assert_eq!(head_commit(&collection).unwrap(), old);
+        assert!(!fixture.dir.join("checkout.reclone").exists());
+
+        // a leftover of a crashed swap doesn't get in the way
+        collection.repository = fixture.collection(Diverge::Reclone).repository;
+        std::fs::create_dir_all(fixture.dir.join("checkout.old")).unwrap();
+        clone_aside(&collection, |_| Ok(())).unwrap();
+        assert_eq!(head_commit(&collection).unwrap(), old);
+        assert!(!fixture.dir.join("checkout.old").exists());
     }
 }
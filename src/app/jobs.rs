@@ -0,0 +1,161 @@
+use crate::app::deploys::DeployLog;
+use crate::handlers::content::SharedState;
+use crate::handlers::update::{deploy_push, Deployed};
+use crate::handlers::webhook::Push;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// Finished jobs kept around for the status endpoint.
+const FINISHED: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// A deploy requested by a webhook.
+#[derive(Debug, Clone, Serialize)]
+pub struct Job {
+    pub id: u64,
+    #[serde(skip)]
+    pub site: usize,
+    pub status: Status,
+    #[serde(flatten)]
+    pub push: Push,
+    pub collections: Vec<Deployed>,
+    /// Set when the deploy itself crashed.
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct Queue {
+    last: u64,
+    jobs: VecDeque<Job>,
+}
+
+/// Deploy jobs of all sites, run one after another by a single worker.
+#[derive(Clone, Default)]
+pub struct Jobs {
+    queue: Arc<Mutex<Queue>>,
+    wake: Arc<Notify>,
+}
+
+impl Jobs {
+    fn queue(&self) -> std::sync::MutexGuard<'_, Queue> {
+        self.queue.lock().expect("job queue poisoned")
+    }
+
+    /// Queue a deploy of `push`. A job for the same repository that hasn't
+    /// started yet takes the push instead, its pull will fetch both.
+    pub fn submit(&self, site: usize, push: Push) -> Job {
+        let mut queue = self.queue();
+        let pending = queue.jobs.iter_mut().find(|job| {
+            job.status == Status::Queued
+                && job.site == site
+                && job.push.full_name == push.full_name
+                && job.push.urls == push.urls
+        });
+        if let Some(job) = pending {
+            println!("Adding push to queued job {}", job.id);
+            for git_ref in push.refs {
+                if !job.push.refs.contains(&git_ref) {
+                    job.push.refs.push(git_ref);
+                }
+            }
+            return job.clone();
+        }
+
+        queue.last += 1;
+        let job = Job {
+            id: queue.last,
+            site,
+            status: Status::Queued,
+            push,
+            collections: Vec::new(),
+            error: None,
+        };
+        queue.jobs.push_back(job.clone());
+        // forget the oldest finished jobs, queued and running ones are never dropped
+        while queue.jobs.iter().filter(|job| finished(job)).count() > FINISHED {
+            if let Some(oldest) = queue.jobs.iter().position(finished) {
+                queue.jobs.remove(oldest);
+            }
+        }
+        drop(queue);
+        self.wake.notify_one();
+        job
+    }
+
+    /// Job `id` if it belongs to `site`.
+    pub fn get(&self, site: usize, id: u64) -> Option<Job> {
+        self.queue()
+            .jobs
+            .iter()
+            .find(|job| job.id == id && job.site == site)
+            .cloned()
+    }
+
+    fn start_next(&self) -> Option<Job> {
+        let mut queue = self.queue();
+        let job = queue
+            .jobs
+            .iter_mut()
+            .find(|job| job.status == Status::Queued)?;
+        job.status = Status::Running;
+        Some(job.clone())
+    }
+
+    fn finish(&self, id: u64, result: Result<Vec<Deployed>, String>) {
+        let mut queue = self.queue();
+        let Some(job) = queue.jobs.iter_mut().find(|job| job.id == id) else {
+            return;
+        };
+        match result {
+            Ok(collections) => {
+                job.status = match collections.iter().any(|c| c.error.is_some()) {
+                    true => Status::Failed,
+                    false => Status::Succeeded,
+                };
+                job.collections = collections;
+            }
+            Err(e) => {
+                job.status = Status::Failed;
+                job.error = Some(e);
+            }
+        }
+        println!("Deploy job {} {:?}", job.id, job.status);
+    }
+}
+
+fn finished(job: &Job) -> bool {
+    matches!(job.status, Status::Succeeded | Status::Failed)
+}
+
+/// Start the worker that runs queued deploy jobs.
+pub fn spawn(state: SharedState, log: DeployLog) -> Jobs {
+    let jobs = Jobs::default();
+    let worker = jobs.clone();
+    tokio::spawn(async move {
+        loop {
+            while let Some(job) = worker.start_next() {
+                let (state, log) = (state.clone(), log.clone());
+                let (site, push) = (job.site, job.push);
+                let result = tokio::task::spawn_blocking(move || {
+                    state.update(|next| deploy_push(next, site, &push, &log))
+                })
+                .await
+                .map_err(|e| format!("deploy crashed: {}", e));
+                worker.finish(job.id, result);
+            }
+            // a submit before this point leaves a permit, nothing is missed
+            worker.wake.notified().await;
+        }
+    });
+    jobs
+}
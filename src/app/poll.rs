@@ -0,0 +1,59 @@
+use crate::app::deploys::{DeployLog, Trigger};
+use crate::app::git;
+use crate::handlers::content::SharedState;
+use crate::handlers::update::deploy;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+/// Check every collection with a `poll_interval` for new commits and deploy them
+/// like the webhook does.
+pub fn spawn(state: SharedState, log: DeployLog) {
+    let polled: Vec<_> = state
+        .load()
+        .collections
+        .iter()
+        .enumerate()
+        .filter_map(|(i, loaded)| Some((i, loaded.collection.poll_interval?)))
+        .collect();
+
+    for (i, seconds) in polled {
+        let (state, log) = (state.clone(), log.clone());
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(seconds));
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // a failed deploy is only retried once the remote moves again
+            let mut last = None;
+            loop {
+                interval.tick().await;
+                let (state, log) = (state.clone(), log.clone());
+                last = tokio::task::spawn_blocking(move || poll(&state, &log, i, last))
+                    .await
+                    .unwrap_or(last);
+            }
+        });
+    }
+}
+
+fn poll(
+    state: &SharedState,
+    log: &DeployLog,
+    i: usize,
+    last: Option<git2::Oid>,
+) -> Option<git2::Oid> {
+    let collection = state.load().collections[i].collection.clone();
+    let (remote, local) = match git::heads(&collection) {
+        Ok(heads) => heads,
+        Err(e) => {
+            println!("Polling {} failed: {}", collection.name, e.message());
+            return last;
+        }
+    };
+    if Some(remote) == local || Some(remote) == last {
+        return Some(remote);
+    }
+
+    println!("{} moved to {}, updating", collection.name, remote);
+    // deploy logs its own failures
+    let _ = state.update(|next| deploy(&mut next.collections[i], Trigger::Poll, log));
+    Some(remote)
+}
@@ -0,0 +1,94 @@
+use crate::app::config::{Config, SharedConfig};
+use crate::app::watch::Changes;
+use notify::RecursiveMode;
+use std::path::{Path, PathBuf};
+use tokio::signal::unix::{signal, SignalKind};
+
+/// Re-read the config on SIGHUP or whenever the file changes on disk.
+pub fn spawn(path: PathBuf, config: SharedConfig) {
+    // watch the directory, editors replace the file instead of writing to it
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = path.file_name().map(|n| n.to_os_string());
+    let changes = Changes::watch(&dir, RecursiveMode::NonRecursive, move |event| {
+        event
+            .paths
+            .iter()
+            .any(|p| p.file_name().map(|n| n.to_os_string()) == name)
+    });
+    let mut changes = match changes {
+        Ok(changes) => Some(changes),
+        Err(e) => {
+            println!("Not watching {} for changes: {}", dir.display(), e);
+            None
+        }
+    };
+
+    tokio::spawn(async move {
+        let mut hangup = signal(SignalKind::hangup()).expect("Failed to install SIGHUP handler");
+        loop {
+            tokio::select! {
+                Some(()) = hangup.recv() => println!("Got SIGHUP, reloading config"),
+                Some(()) = async { changes.as_mut()?.next().await } => {
+                    println!("{} changed, reloading config", path.display());
+                }
+                else => break,
+            }
+            reload(&path, &config);
+        }
+    });
+}
+
+fn reload(path: &Path, config: &SharedConfig) {
+    let mut new = match Config::from_file(path) {
+        Ok(new) => new,
+        Err(e) => {
+            println!("Config reload failed, keeping the running config: {}", e);
+            return;
+        }
+    };
+
+    for key in pin_restart_only(&config.load(), &mut new) {
+        println!("{} changed, restart required", key);
+    }
+    config.store(new);
+    println!("Config reloaded from {}", path.display());
+}
+
+// Settings that are only read at startup keep their running value, the
+// snapshot should describe what is actually served.
+fn pin_restart_only(running: &Config, new: &mut Config) -> Vec<String> {
+    let mut changed = Vec::new();
+    if running.server.listen != new.server.listen {
+        new.server.listen = running.server.listen.clone();
+        changed.push("server.listen".to_string());
+    }
+    if running.server.port != new.server.port {
+        new.server.port = running.server.port;
+        changed.push("server.port".to_string());
+    }
+    if running.server.deploy_log != new.server.deploy_log
+        || running.server.deploy_log_size != new.server.deploy_log_size
+    {
+        new.server.deploy_log = running.server.deploy_log.clone();
+        new.server.deploy_log_size = running.server.deploy_log_size;
+        changed.push("server.deploy_log".to_string());
+    }
+    // loaded content is tied to the site order, so sites can't come and go
+    if running.sites.len() != new.sites.len() {
+        new.sites = running.sites.clone();
+        changed.push("sites".to_string());
+    }
+    for (i, (running, new)) in running.sites.iter().zip(new.sites.iter_mut()).enumerate() {
+        if running.content.collections != new.content.collections {
+            new.content.collections = running.content.collections.clone();
+            changed.push(match i {
+                0 => "content.collections".to_string(),
+                i => format!("sites.{}.content.collections", i - 1),
+            });
+        }
+    }
+    changed
+}
@@ -0,0 +1,250 @@
+use git2::{Oid, Repository};
+use pgp::composed::{Deserializable, DetachedSignature, SignedPublicKey, SignedPublicSubKey};
+use pgp::packet::{Signature, SignatureType};
+use pgp::types::{KeyDetails, Timestamp};
+
+/// A key deployed commits may be signed with.
+pub enum TrustedKey {
+    Pgp(Box<SignedPublicKey>),
+    Ssh(ssh_key::PublicKey),
+}
+
+// git signs ssh commits in this namespace
+const SSH_NAMESPACE: &str = "git";
+
+fn inline(entry: &str) -> bool {
+    entry.contains("-----BEGIN PGP PUBLIC KEY BLOCK-----")
+        || ssh_key::PublicKey::from_openssh(entry.trim()).is_ok()
+}
+
+/// Keys of an entry in `trusted_keys`, either the key itself or a file holding
+/// armored OpenPGP keys or ssh public keys, one per line.
+pub fn parse(entry: &str) -> Result<Vec<TrustedKey>, String> {
+    let text = if inline(entry) {
+        entry.to_string()
+    } else {
+        std::fs::read_to_string(entry).map_err(|e| format!("could not read {}: {}", entry, e))?
+    };
+
+    if text.contains("-----BEGIN PGP PUBLIC KEY BLOCK-----") {
+        let (keys, _) = SignedPublicKey::from_string_many(&text)
+            .map_err(|e| format!("invalid OpenPGP key: {}", e))?;
+        return keys
+            .map(|key| {
+                let key = key.map_err(|e| format!("invalid OpenPGP key: {}", e))?;
+                // a subkey only counts if the primary key vouches for it
+                key.verify_bindings()
+                    .map_err(|e| format!("invalid OpenPGP key: {}", e))?;
+                Ok(TrustedKey::Pgp(Box::new(key)))
+            })
+            .collect();
+    }
+
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            ssh_key::PublicKey::from_openssh(line)
+                .map(TrustedKey::Ssh)
+                .map_err(|e| format!("invalid ssh key: {}", e))
+        })
+        .collect()
+}
+
+// a key `created` then that expires `expires` seconds later is still good `at`, 0 never expires
+fn alive(created: Timestamp, expires: Option<u32>, at: Timestamp) -> bool {
+    match expires {
+        Some(secs) if secs > 0 => {
+            u64::from(at.as_secs()) < u64::from(created.as_secs()) + u64::from(secs)
+        }
+        _ => true,
+    }
+}
+
+// the newest of `signatures` of type `kind`
+fn newest(signatures: &[Signature], kind: SignatureType) -> Option<&Signature> {
+    signatures
+        .iter()
+        .filter(|sig| sig.typ() == Some(kind))
+        .max_by_key(|sig| sig.created().map(Timestamp::as_secs))
+}
+
+// the primary key is neither revoked nor expired when the commit was signed `at`
+fn valid(key: &SignedPublicKey, at: Timestamp) -> bool {
+    if !key.details.revocation_signatures.is_empty() {
+        return false;
+    }
+    let expires = key
+        .details
+        .users
+        .iter()
+        .flat_map(|user| user.signatures.iter())
+        .chain(key.details.direct_signatures.iter())
+        .max_by_key(|sig| sig.created().map(Timestamp::as_secs))
+        .and_then(Signature::key_expiration_time)
+        .map(|d| d.as_secs());
+    alive(key.primary_key.created_at(), expires, at)
+}
+
+// a subkey only signs if its binding says so, it isn't revoked and hadn't expired `at`
+fn can_sign(subkey: &SignedPublicSubKey, at: Timestamp) -> bool {
+    if newest(&subkey.signatures, SignatureType::SubkeyRevocation).is_some() {
+        return false;
+    }
+    let Some(binding) = newest(&subkey.signatures, SignatureType::SubkeyBinding) else {
+        return false;
+    };
+    binding.key_flags().sign()
+        && alive(
+            subkey.key.created_at(),
+            binding.key_expiration_time().map(|d| d.as_secs()),
+            at,
+        )
+}
+
+/// Fails unless `commit` carries a signature by one of `keys`.
+pub fn verify(repo: &Repository, commit: Oid, keys: &[TrustedKey]) -> Result<(), git2::Error> {
+    let reject = |why: &str| git2::Error::from_str(&format!("refusing commit {}: {}", commit, why));
+
+    let (signature, data) = match repo.extract_signature(&commit, None) {
+        Ok(extracted) => extracted,
+        Err(e) if e.code() == git2::ErrorCode::NotFound => return Err(reject("not signed")),
+        Err(e) => return Err(e),
+    };
+    let signature = signature
+        .as_str()
+        .ok_or_else(|| reject("malformed signature"))?;
+
+    let trusted = if signature.contains("-----BEGIN SSH SIGNATURE-----") {
+        let signature =
+            ssh_key::SshSig::from_pem(signature).map_err(|_| reject("malformed signature"))?;
+        keys.iter().any(|key| match key {
+            TrustedKey::Ssh(key) => key.verify(SSH_NAMESPACE, &data, &signature).is_ok(),
+            TrustedKey::Pgp(_) => false,
+        })
+    } else {
+        let (signature, _) =
+            DetachedSignature::from_string(signature).map_err(|_| reject("malformed signature"))?;
+        let at = signature.signature.created().unwrap_or_else(Timestamp::now);
+        keys.iter().any(|key| match key {
+            TrustedKey::Pgp(key) if valid(key, at) => {
+                signature.verify(key.as_ref(), &data).is_ok()
+                    || key
+                        .public_subkeys
+                        .iter()
+                        .filter(|subkey| can_sign(subkey, at))
+                        .any(|subkey| signature.verify(subkey, &data).is_ok())
+            }
+            TrustedKey::Pgp(_) | TrustedKey::Ssh(_) => false,
+        })
+    };
+
+    match trusted {
+        true => Ok(()),
+        false => Err(reject("signature does not match a trusted key")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::fixture::Fixture;
+
+    // signed with gpg and ssh-keygen, see the key and signature files next to it
+    macro_rules! fixture {
+        ($name:literal) => {
+            include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/fixtures/signature/",
+                $name
+            ))
+        };
+    }
+
+    const COMMIT: &str = fixture!("commit");
+
+    // the fixture commit written to a fresh repository, with `signature` if any
+    fn commit(fixture: &Fixture, signature: Option<&str>) -> Oid {
+        let repo = &fixture.remote;
+        // the commit points at the empty tree
+        repo.treebuilder(None).unwrap().write().unwrap();
+        match signature {
+            Some(signature) => repo.commit_signed(COMMIT, signature, None).unwrap(),
+            None => repo
+                .odb()
+                .unwrap()
+                .write(git2::ObjectType::Commit, COMMIT.as_bytes())
+                .unwrap(),
+        }
+    }
+
+    fn check(keys: &str, signature: Option<&str>) -> Result<(), String> {
+        let fixture = Fixture::new();
+        let id = commit(&fixture, signature);
+        verify(&fixture.remote, id, &parse(keys).unwrap()).map_err(|e| e.message().to_string())
+    }
+
+    #[test]
+    fn ssh() {
+        let trusted = fixture!("ssh_trusted.pub");
+        assert!(check(trusted, Some(fixture!("ssh_trusted.sig"))).is_ok());
+        assert!(check(trusted, Some(fixture!("ssh_other.sig")))
+            .unwrap_err()
+            .ends_with("signature does not match a trusted key"));
+        // signed for something other than git
+        assert!(check(trusted, Some(fixture!("ssh_namespace.sig"))).is_err());
+        assert!(check(trusted, None).unwrap_err().ends_with("not signed"));
+        // an OpenPGP key never matches an ssh signature
+        assert!(check(
+            fixture!("trusted.pub.asc"),
+            Some(fixture!("ssh_trusted.sig"))
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn pgp() {
+        let trusted = fixture!("trusted.pub.asc");
+        assert!(check(trusted, Some(fixture!("primary.asc"))).is_ok());
+        assert!(check(trusted, Some(fixture!("subkey.asc"))).is_ok());
+        // made while the subkey was still valid
+        assert!(check(trusted, Some(fixture!("expiring.asc"))).is_ok());
+        assert!(check(trusted, Some(fixture!("revoked.asc"))).is_err());
+        assert!(check(trusted, Some(fixture!("other.asc")))
+            .unwrap_err()
+            .ends_with("signature does not match a trusted key"));
+        assert!(check(trusted, None).unwrap_err().ends_with("not signed"));
+        assert!(check(fixture!("ssh_trusted.pub"), Some(fixture!("primary.asc"))).is_err());
+    }
+
+    #[test]
+    fn pgp_subkeys() {
+        let keys = parse(fixture!("trusted.pub.asc")).unwrap();
+        let TrustedKey::Pgp(key) = &keys[0] else {
+            panic!("not an OpenPGP key");
+        };
+        // all created 2020-01-01, the second expires a day later
+        let at = |secs: u32| Timestamp::from_secs(1577836800 + secs);
+        let [signing, expiring, revoked, encryption] = &key.public_subkeys[..] else {
+            panic!("expected four subkeys");
+        };
+        assert!(can_sign(signing, at(3600)));
+        assert!(can_sign(expiring, at(3600)));
+        assert!(!can_sign(expiring, at(2 * 86400)));
+        assert!(!can_sign(revoked, at(3600)));
+        assert!(!can_sign(encryption, at(3600)));
+        assert!(valid(key, at(10 * 365 * 86400)));
+    }
+
+    #[test]
+    fn parse_entries() {
+        let line = fixture!("ssh_trusted.pub").trim();
+        assert_eq!(parse(line).unwrap().len(), 1);
+        assert_eq!(parse(fixture!("trusted.pub.asc")).unwrap().len(), 1);
+        assert!(parse("/nonexistent/keys.asc")
+            .err()
+            .unwrap()
+            .starts_with("could not read"));
+        assert!(parse("-----BEGIN PGP PUBLIC KEY BLOCK-----\ngarbage").is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use serde::de::DeserializeOwned;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use yaml_rust2::parser::{MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::Marker;
+use yaml_rust2::Event;
+
+/// A single problem found in the config, pointing at the offending key.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub key: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn from_yaml(e: &serde_yaml::Error) -> Problem {
+        let (line, column) = e
+            .location()
+            .map(|l| (l.line(), l.column()))
+            .unwrap_or((1, 1));
+        Problem {
+            key: String::new(),
+            line,
+            column,
+            message: e.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: ", self.line, self.column)?;
+        if !self.key.is_empty() {
+            write!(f, "{}: ", self.key)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+enum Frame {
+    Map { path: String, key: Option<String> },
+    Seq { path: String, index: usize },
+}
+
+// Records the position of every key (and sequence entry) while walking the
+// event stream, serde_yaml does not expose those once it built a `Value`.
+#[derive(Default)]
+struct Locator {
+    frames: Vec<Frame>,
+    locations: HashMap<String, (usize, usize)>,
+}
+
+pub fn join(parent: &str, child: &str) -> String {
+    if parent.is_empty() {
+        child.to_string()
+    } else {
+        format!("{}.{}", parent, child)
+    }
+}
+
+impl Locator {
+    fn record(&mut self, path: &str, mark: Marker) {
+        self.locations
+            .entry(path.to_string())
+            .or_insert((mark.line(), mark.col() + 1));
+    }
+
+    // path of the value node starting at `mark`
+    fn value(&mut self, mark: Marker) -> String {
+        let (path, record) = match self.frames.last_mut() {
+            Some(Frame::Map { key, .. }) => (key.take().unwrap_or_default(), false),
+            Some(Frame::Seq { path, index }) => {
+                *index += 1;
+                (join(path, &(*index - 1).to_string()), true)
+            }
+            None => (String::new(), false),
+        };
+        if record {
+            self.record(&path, mark);
+        }
+        path
+    }
+}
+
+impl MarkedEventReceiver for Locator {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::Scalar(ref name, ..) => {
+                if let Some(Frame::Map {
+                    path,
+                    key: key @ None,
+                }) = self.frames.last_mut()
+                {
+                    let path = join(path, name);
+                    *key = Some(path.clone());
+                    self.record(&path, mark);
+                } else {
+                    self.value(mark);
+                }
+            }
+            Event::Alias(_) => {
+                self.value(mark);
+            }
+            Event::MappingStart(..) => {
+                let path = self.value(mark);
+                self.frames.push(Frame::Map { path, key: None });
+            }
+            Event::SequenceStart(..) => {
+                let path = self.value(mark);
+                self.frames.push(Frame::Seq { path, index: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.frames.pop();
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Deserialize the way serde_yaml reads the file, a plain scalar like `8080`
+/// fits both a string and a number field. `from_value` is stricter than that.
+pub fn deserialize<T: DeserializeOwned>(value: &Value) -> Result<T, serde_yaml::Error> {
+    serde_yaml::from_str(&serde_yaml::to_string(value)?)
+}
+
+// value for the key or its closest parent that has one
+fn closest<'m, T>(map: &'m HashMap<String, T>, key: &str) -> Option<&'m T> {
+    let mut key = key;
+    loop {
+        if let Some(value) = map.get(key) {
+            return Some(value);
+        }
+        key = &key[..key.rfind('.')?];
+    }
+}
+
+// edit distance between two keys, small enough to not bother with a crate
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let next = (diagonal + usize::from(ca != *cb))
+                .min(row[j] + 1)
+                .min(row[j + 1] + 1);
+            diagonal = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+/// Walks a parsed config and collects every problem instead of stopping at the first.
+pub struct Validator<'a> {
+    root: &'a Value,
+    locations: HashMap<String, (usize, usize)>,
+    sources: HashMap<String, String>,
+    aliases: Vec<(String, String)>,
+    problems: Vec<Problem>,
+}
+
+impl<'a> Validator<'a> {
+    /// `sources` names the environment variable behind overridden keys.
+    pub fn new(text: &str, root: &'a Value, sources: HashMap<String, String>) -> Validator<'a> {
+        let mut locator = Locator::default();
+        // the text already parsed through serde_yaml, a scan error only costs locations
+        let _ = Parser::new_from_str(text).load(&mut locator, false);
+        Validator {
+            root,
+            locations: locator.locations,
+            sources,
+            aliases: Vec::new(),
+            problems: Vec::new(),
+        }
+    }
+
+    /// Report problems below `key` as `alias`, the name it has in the file.
+    pub fn alias(&mut self, key: String, alias: String) {
+        self.aliases.push((key, alias));
+    }
+
+    pub fn error(&mut self, key: &str, message: impl Into<String>) {
+        let key = &self
+            .aliases
+            .iter()
+            .find_map(|(from, to)| {
+                let rest = key.strip_prefix(from.as_str())?;
+                (rest.is_empty() || rest.starts_with('.')).then(|| format!("{}{}", to, rest))
+            })
+            .unwrap_or_else(|| key.to_string());
+        let (line, column) = closest(&self.locations, key).copied().unwrap_or((1, 1));
+        let mut message = message.into();
+        if let Some(var) = closest(&self.sources, key) {
+            message = format!("{} (set by {})", message, var);
+        }
+        self.problems.push(Problem {
+            key: key.to_string(),
+            line,
+            column,
+            message,
+        });
+    }
+
+    /// Look up a dotted key, numeric segments index into sequences. The
+    /// empty key is the root.
+    pub fn lookup(&self, key: &str) -> Option<&'a Value> {
+        if key.is_empty() {
+            return Some(self.root);
+        }
+        key.split('.')
+            .try_fold(self.root, |value, segment| match value {
+                Value::Sequence(seq) => seq.get(segment.parse::<usize>().ok()?),
+                _ => value.get(segment),
+            })
+    }
+
+    pub fn optional<T: DeserializeOwned>(&mut self, key: &str) -> Option<T> {
+        match self.lookup(key) {
+            None | Some(Value::Null) => None,
+            Some(value) => match deserialize(value) {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    self.error(key, e.to_string());
+                    None
+                }
+            },
+        }
+    }
+
+    pub fn required<T: DeserializeOwned>(&mut self, key: &str) -> Option<T> {
+        if matches!(self.lookup(key), None | Some(Value::Null)) {
+            self.error(key, "missing required field");
+            return None;
+        }
+        self.optional(key)
+    }
+
+    /// Require a mapping at `key`, returns whether its fields are worth checking.
+    pub fn section(&mut self, key: &str) -> bool {
+        match self.lookup(key) {
+            Some(Value::Mapping(_)) => true,
+            None | Some(Value::Null) => {
+                self.error(key, "missing required section");
+                false
+            }
+            Some(_) => {
+                self.error(key, "expected a mapping");
+                false
+            }
+        }
+    }
+
+    /// Report keys of the mapping at `key` that aren't among `fields`, mostly typos.
+    pub fn known(&mut self, key: &str, fields: &[&str]) {
+        let Some(Value::Mapping(map)) = self.lookup(key) else {
+            return;
+        };
+        for name in map.keys() {
+            let name = match name {
+                Value::String(name) => name.clone(),
+                other => serde_yaml::to_string(other)
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+            };
+            if fields.contains(&name.as_str()) {
+                continue;
+            }
+            let message = match fields.iter().find(|field| distance(field, &name) <= 2) {
+                Some(field) => format!("unknown key, did you mean '{}'?", field),
+                None => format!("unknown key, expected one of {}", fields.join(", ")),
+            };
+            self.error(&join(key, &name), message);
+        }
+    }
+
+    pub fn url(&mut self, key: &str, value: &str) {
+        match url::Url::parse(value) {
+            Ok(url) if !matches!(url.scheme(), "http" | "https") => {
+                self.error(key, format!("unsupported scheme '{}'", url.scheme()))
+            }
+            Ok(url) if url.host().is_none() => self.error(key, "url has no host"),
+            Ok(_) => (),
+            Err(e) => self.error(key, format!("invalid url '{}': {}", value, e)),
+        }
+    }
+
+    /// Git remotes are urls, scp-like `user@host:path` or local paths.
+    pub fn repository(&mut self, key: &str, value: &str) {
+        if value.contains("://") {
+            match url::Url::parse(value) {
+                Ok(url) if url.scheme() == "file" => {
+                    if !Path::new(url.path()).exists() {
+                        self.error(key, format!("repository {} does not exist", value));
+                    }
+                }
+                Ok(_) => (),
+                Err(e) => self.error(key, format!("invalid repository url '{}': {}", value, e)),
+            }
+        } else if value
+            .split_once(':')
+            .is_some_and(|(host, _)| !host.contains('/'))
+        {
+            // scp-like syntax, reachability is only known once we fetch
+        } else if !Path::new(value).exists() {
+            self.error(key, format!("repository {} does not exist", value));
+        }
+    }
+
+    /// The content path has to be a directory or creatable as one.
+    pub fn directory(&mut self, key: &str, path: &Path) {
+        if path.exists() {
+            if !path.is_dir() {
+                self.error(key, format!("{} is not a directory", path.display()));
+            }
+            return;
+        }
+        match path.ancestors().skip(1).find(|p| p.exists()) {
+            Some(parent) if !parent.is_dir() => self.error(
+                key,
+                format!("{} can not be created below a file", path.display()),
+            ),
+            _ => (),
+        }
+    }
+
+    pub fn into_problems(mut self) -> Vec<Problem> {
+        self.problems.sort_by_key(|p| (p.line, p.column));
+        self.problems
+    }
+}
@@ -0,0 +1,90 @@
+use crate::handlers::content::{Pages, SharedState};
+use notify::{RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// File system events below a path, folded into one notification per burst.
+pub struct Changes {
+    // dropping the watcher stops it
+    _watcher: notify::RecommendedWatcher,
+    rx: mpsc::Receiver<()>,
+}
+
+impl Changes {
+    /// Watch `path` for events that `relevant` accepts, reads are never relevant.
+    pub fn watch(
+        path: &Path,
+        mode: RecursiveMode,
+        relevant: impl Fn(&notify::Event) -> bool + Send + 'static,
+    ) -> notify::Result<Changes> {
+        let (tx, rx) = mpsc::channel::<()>(1);
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                if res.is_ok_and(|event| !event.kind.is_access() && relevant(&event)) {
+                    // a reload is already pending if the channel is full
+                    let _ = tx.try_send(());
+                }
+            })?;
+        watcher.watch(path, mode)?;
+        Ok(Changes {
+            _watcher: watcher,
+            rx,
+        })
+    }
+
+    /// Wait for the next change, `None` once the watcher is gone.
+    pub async fn next(&mut self) -> Option<()> {
+        self.rx.recv().await?;
+        // editors write in several steps, give them a moment to finish
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        while self.rx.try_recv().is_ok() {}
+        Some(())
+    }
+}
+
+/// Reload collections without a repository whenever their directory changes.
+pub fn spawn(state: SharedState) {
+    let local: Vec<_> = state
+        .load()
+        .collections
+        .iter()
+        .enumerate()
+        .filter(|(_, loaded)| loaded.collection.repository.is_none())
+        .map(|(i, loaded)| (i, loaded.collection.clone()))
+        .collect();
+
+    for (i, collection) in local {
+        let mut changes = match Changes::watch(&collection.path, RecursiveMode::Recursive, |_| true)
+        {
+            Ok(changes) => changes,
+            Err(e) => {
+                println!(
+                    "Not watching {} for changes: {}",
+                    collection.path.display(),
+                    e
+                );
+                continue;
+            }
+        };
+
+        let state = state.clone();
+        tokio::spawn(async move {
+            while changes.next().await.is_some() {
+                println!(
+                    "{} changed, reloading {}",
+                    collection.path.display(),
+                    collection.name
+                );
+                let state = state.clone();
+                let collection = collection.clone();
+                let _ = tokio::task::spawn_blocking(move || {
+                    let pages = Arc::new(Pages::load(&collection));
+                    state.update(|next| next.collections[i].pages = pages);
+                })
+                .await;
+            }
+        });
+    }
+}
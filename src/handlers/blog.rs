@@ -1,18 +1,17 @@
-use crate::app::config::IndexPage;
-use crate::handlers::post;
+use crate::app::config::{Collection, Site};
+use crate::app::git::Authorship;
+use crate::handlers::{content::Nav, post};
 use askama_axum::Template;
-use axum::{
-    extract::{Extension, Path},
-    http::StatusCode,
-};
-use std::sync::{Arc, Mutex};
+use axum::http::StatusCode;
 
 #[derive(Template)]
 #[template(path = "blog.html")]
 pub struct BlogIndex {
     pub posts: Vec<post::PostList>,
+    pub prefix: String,
     pub site: String,
     pub title: Option<String>,
+    pub nav: Vec<Nav>,
 }
 
 #[derive(Template)]
@@ -20,42 +19,40 @@ pub struct BlogIndex {
 pub struct BlogPost {
     pub content: String,
     pub metadata: post::Metadata,
+    pub authorship: Option<Authorship>,
     pub site: String,
     pub title: Option<String>,
+    pub nav: Vec<Nav>,
+    /// Commit the shown post is taken from, the deployed one if unset.
+    pub revision: Option<String>,
 }
 
-pub async fn blog(
-    Extension(posts): Extension<Arc<Mutex<post::ContextState>>>,
-    Extension(site): Extension<String>,
-    Extension(index): Extension<IndexPage>,
-) -> BlogIndex {
-    let post_list = posts.lock().unwrap().posts.clone();
+pub fn blog(posts: &[post::PostList], collection: &Collection, site: &Site) -> BlogIndex {
     BlogIndex {
-        posts: post_list,
-        site,
-        title: index.title,
+        posts: posts.to_vec(),
+        prefix: collection.prefix.clone(),
+        site: site.host.clone(),
+        title: site.index.title.clone(),
+        nav: crate::handlers::content::nav(site),
     }
 }
 
-pub async fn blog_post(
-    Path(title): Path<String>,
-    Extension(posts): Extension<Arc<Mutex<post::ContextState>>>,
-    Extension(site): Extension<String>,
-    Extension(index): Extension<IndexPage>,
+pub fn blog_post(
+    posts: &[post::PostList],
+    title: &str,
+    site: &Site,
 ) -> Result<BlogPost, StatusCode> {
     // not the best way...
     // TODO change this to a hashmap
-    let post_list = posts.lock().unwrap().posts.clone();
-    match post_list
-        .iter()
-        .find(|&i| i.metadata.title == title)
-        .cloned()
-    {
+    match posts.iter().find(|&i| i.metadata.title == title).cloned() {
         Some(post_entry) => Ok(BlogPost {
             content: post_entry.content,
             metadata: post_entry.metadata,
-            site,
-            title: index.title,
+            authorship: post_entry.authorship,
+            site: site.host.clone(),
+            title: site.index.title.clone(),
+            nav: crate::handlers::content::nav(site),
+            revision: None,
         }),
         None => Err(StatusCode::NOT_FOUND),
     }
@@ -0,0 +1,334 @@
+use crate::app::config::{Collection, Config, Kind, Site};
+use crate::app::git;
+use crate::handlers::{
+    blog,
+    history::{self, View},
+    post::{self, PostList},
+    site::CurrentSite,
+    status,
+    wiki::{self, WikiPost},
+};
+use axum::{
+    extract::{Extension, Request},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use glob::glob;
+use percent_encoding::percent_decode_str;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use tower::ServiceExt;
+use tower_http::services::ServeDir;
+
+/// Parsed pages of a single collection.
+#[derive(Clone)]
+pub enum Pages {
+    Blog(Vec<PostList>),
+    Wiki(Vec<WikiPost>),
+}
+
+impl Pages {
+    pub fn load(collection: &Collection) -> Pages {
+        // local collections have no history
+        let log = git::authorship(collection).unwrap_or_default();
+        let files = sources(collection);
+        match collection.kind {
+            Kind::Blog => Pages::Blog(post::load(&files, &log).unwrap_or_default()),
+            Kind::Wiki => Pages::Wiki(wiki::load(&files, &log).unwrap_or_default()),
+        }
+    }
+
+    /// Load the collection only if `check` finds nothing wrong with it.
+    pub fn load_checked(collection: &Collection) -> Result<Pages, String> {
+        let problems = check(collection);
+        if !problems.is_empty() {
+            return Err(problems.join("; "));
+        }
+        // a page we didn't foresee must not take the deploy down with it
+        std::panic::catch_unwind(|| Pages::load(collection))
+            .map_err(|_| "loading the pages panicked".to_string())
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Pages::Blog(posts) => posts.len(),
+            Pages::Wiki(pages) => pages.len(),
+        }
+    }
+}
+
+/// A markdown file of a collection.
+pub struct Source {
+    /// Path inside the collection.
+    pub location: PathBuf,
+    pub text: Result<String, String>,
+}
+
+/// Problems `Pages::load` would paper over or panic on, what `artemis check` reports.
+pub fn check(collection: &Collection) -> Vec<String> {
+    let files = sources(collection);
+    match collection.kind {
+        Kind::Blog => post::check(&files),
+        Kind::Wiki => wiki::check(&files),
+    }
+}
+
+/// Markdown files of a collection, read from the deployed commit for bare
+/// repositories. Blogs only use the top level, wikis every folder.
+pub fn sources(collection: &Collection) -> Vec<Source> {
+    let recursive = collection.kind == Kind::Wiki;
+    if collection.bare {
+        return match git::markdown_files(collection, recursive) {
+            Ok(files) => files
+                .into_iter()
+                .map(|(location, bytes)| Source {
+                    location,
+                    text: String::from_utf8(bytes)
+                        .map_err(|_| "stream did not contain valid UTF-8".to_string()),
+                })
+                .collect(),
+            Err(e) => {
+                println!("Could not read {}: {}", collection.name, e.message());
+                Vec::new()
+            }
+        };
+    }
+
+    let pattern = if recursive { "**/*.md" } else { "*.md" };
+    if recursive {
+        println!("Reading wiki from {:#?}", &collection.path);
+    }
+    glob(&format!(
+        "{}/{}",
+        collection.path.to_str().unwrap(),
+        pattern
+    ))
+    .expect("Failed to read pattern")
+    .filter_map(Result::ok)
+    .map(|path| Source {
+        location: path
+            .strip_prefix(&collection.path)
+            .unwrap_or(&path)
+            .to_path_buf(),
+        text: std::fs::read_to_string(&path).map_err(|e| e.to_string()),
+    })
+    .collect()
+}
+
+#[derive(Clone)]
+pub struct Loaded {
+    /// Index of the site in `Config::sites`.
+    pub site: usize,
+    pub collection: Collection,
+    /// Shared between snapshots until the collection is reloaded.
+    pub pages: Arc<Pages>,
+}
+
+#[derive(Clone)]
+pub struct ContextState {
+    pub collections: Vec<Loaded>,
+}
+
+/// The loaded content, replaced as a whole so readers never wait for a deploy.
+#[derive(Clone)]
+pub struct SharedState {
+    current: Arc<RwLock<Arc<ContextState>>>,
+    writer: Arc<Mutex<()>>,
+}
+
+impl SharedState {
+    pub fn new(state: ContextState) -> SharedState {
+        SharedState {
+            current: Arc::new(RwLock::new(Arc::new(state))),
+            writer: Arc::new(Mutex::new(())),
+        }
+    }
+
+    pub fn load(&self) -> Arc<ContextState> {
+        self.current.read().expect("state lock poisoned").clone()
+    }
+
+    /// Let `f` change a copy of the current state and swap it in afterwards,
+    /// nothing changes if `f` panics. Blocks, run it off the async runtime.
+    /// Updates run one after another so none is lost.
+    pub fn update<R>(&self, f: impl FnOnce(&mut ContextState) -> R) -> R {
+        // a panicking update never got to swap, the state it leaves behind is fine
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut next = ContextState::clone(&self.load());
+        let result = f(&mut next);
+        *self.current.write().expect("state lock poisoned") = Arc::new(next);
+        result
+    }
+}
+
+impl ContextState {
+    /// Load every collection. What `check` finds is only logged here, a
+    /// deploy rejects it but one broken page must not take every site down.
+    /// A collection whose pages can't be loaded at all is served empty.
+    pub fn load(config: &Config) -> ContextState {
+        let mut collections = Vec::new();
+        for (site, settings) in config.sites.iter().enumerate() {
+            for collection in &settings.content.collections {
+                let problems = check(collection);
+                if !problems.is_empty() {
+                    println!(
+                        "{}: serving content with problems, {}",
+                        collection.name,
+                        problems.join("; ")
+                    );
+                }
+                let pages =
+                    std::panic::catch_unwind(|| Pages::load(collection)).unwrap_or_else(|_| {
+                        println!(
+                            "{}: loading the pages panicked, serving none",
+                            collection.name
+                        );
+                        match collection.kind {
+                            Kind::Blog => Pages::Blog(Vec::new()),
+                            Kind::Wiki => Pages::Wiki(Vec::new()),
+                        }
+                    });
+                collections.push(Loaded {
+                    site,
+                    collection: collection.clone(),
+                    pages: Arc::new(pages),
+                });
+            }
+        }
+        ContextState { collections }
+    }
+}
+
+/// Entry of the main navigation.
+#[derive(Clone, Debug, Default)]
+pub struct Nav {
+    pub name: String,
+    pub prefix: String,
+}
+
+pub fn nav(site: &Site) -> Vec<Nav> {
+    site.content
+        .collections
+        .iter()
+        .map(|collection| {
+            let mut name = collection.name.chars();
+            Nav {
+                name: name
+                    .next()
+                    .map(|first| first.to_uppercase().chain(name).collect())
+                    .unwrap_or_default(),
+                prefix: collection.prefix.clone(),
+            }
+        })
+        .collect()
+}
+
+// remainder of `path` below `prefix`, empty for the prefix itself
+fn below(path: &str, prefix: &str) -> Option<String> {
+    let rest = path.strip_prefix(prefix)?;
+    if rest.is_empty() {
+        Some(String::new())
+    } else {
+        rest.strip_prefix('/').map(str::to_string)
+    }
+}
+
+/// Serves every collection below its prefix, anything else is a 404.
+pub async fn dispatch(
+    current: CurrentSite,
+    Extension(state): Extension<SharedState>,
+    request: Request,
+) -> Response {
+    let site = current.site();
+    let state = state.load();
+
+    // images first so an image folder never turns into a post lookup
+    let images = state.collections.iter().find_map(|loaded| {
+        if loaded.site != current.index {
+            return None;
+        }
+        let rest = below(request.uri().path(), &loaded.collection.images())?;
+        Some((loaded.collection.clone(), rest))
+    });
+    if let Some((collection, rest)) = images {
+        if collection.bare {
+            return tokio::task::spawn_blocking(move || blob_image(&collection, &rest))
+                .await
+                .unwrap_or_else(|_| status::internal_error().into_response());
+        }
+        return image(collection.path.join("images"), rest, request).await;
+    }
+
+    let path = percent_decode_str(request.uri().path())
+        .decode_utf8_lossy()
+        .to_string();
+    let view = View::from_query(request.uri().query());
+    let found = state
+        .collections
+        .iter()
+        .filter(|loaded| loaded.site == current.index)
+        .filter_map(|loaded| Some((loaded, below(&path, &loaded.collection.prefix)?)))
+        .max_by_key(|(loaded, _)| loaded.collection.prefix.len());
+    let Some((loaded, rest)) = found else {
+        return status::code_404().await.into_response();
+    };
+
+    match (view, loaded.pages.as_ref()) {
+        // reads the git object database
+        (Some(view), _) => {
+            let loaded = loaded.clone();
+            tokio::task::spawn_blocking(move || {
+                history::render(view, &loaded, &rest, current.site())
+            })
+            .await
+            .unwrap_or_else(|_| status::internal_error().into_response())
+        }
+        (None, Pages::Blog(posts)) if rest.is_empty() => {
+            blog::blog(posts, &loaded.collection, site).into_response()
+        }
+        (None, Pages::Blog(posts)) => blog::blog_post(posts, &rest, site).into_response(),
+        (None, Pages::Wiki(pages)) => {
+            wiki::wiki_posts(pages, rest, &loaded.collection, site).into_response()
+        }
+    }
+}
+
+// images of a bare repository come from the deployed commit
+fn blob_image(collection: &Collection, rest: &str) -> Response {
+    let rest = percent_decode_str(rest).decode_utf8_lossy();
+    match git::file_bytes(
+        collection,
+        &std::path::Path::new("images").join(rest.as_ref()),
+    ) {
+        Ok(bytes) => (
+            [(
+                header::CONTENT_TYPE,
+                mime_guess::from_path(rest.as_ref())
+                    .first_or_octet_stream()
+                    .to_string(),
+            )],
+            bytes,
+        )
+            .into_response(),
+        Err(_) => status::ErrorHandler {
+            code: StatusCode::NOT_FOUND,
+            msg: "no route for uri".to_string(),
+        }
+        .into_response(),
+    }
+}
+
+async fn image(dir: PathBuf, rest: String, request: Request) -> Response {
+    let (mut parts, body) = request.into_parts();
+    parts.uri = match format!("/{}", rest).parse() {
+        Ok(uri) => uri,
+        Err(_) => return status::code_404().await.into_response(),
+    };
+    match ServeDir::new(dir)
+        .oneshot(Request::from_parts(parts, body))
+        .await
+    {
+        Ok(response) => response.into_response(),
+        Err(e) => match e {},
+    }
+}
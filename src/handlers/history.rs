@@ -0,0 +1,146 @@
+use crate::app::config::Site;
+use crate::app::git::{self, DiffLine, Revision};
+use crate::handlers::{
+    blog::BlogPost,
+    content::{self, Loaded, Nav, Pages},
+    post, status, wiki,
+};
+use askama_axum::Template;
+use axum::response::{IntoResponse, Response};
+use std::path::Path;
+
+/// Alternative views of a page, picked by the query string.
+#[derive(Debug, Clone, PartialEq)]
+pub enum View {
+    /// `?history`
+    History,
+    /// `?rev=<sha>`
+    Rev(String),
+    /// `?diff=<old>..<new>`
+    Diff(String, String),
+}
+
+impl View {
+    pub fn from_query(query: Option<&str>) -> Option<View> {
+        url::form_urlencoded::parse(query?.as_bytes()).find_map(|(key, value)| match key.as_ref() {
+            "history" => Some(View::History),
+            "rev" => Some(View::Rev(value.into_owned())),
+            "diff" => value
+                .split_once("..")
+                .map(|(old, new)| View::Diff(old.to_string(), new.to_string())),
+            _ => None,
+        })
+    }
+}
+
+#[derive(Template)]
+#[template(path = "history.html")]
+pub struct History {
+    /// Url of the page, the revisions link below it.
+    pub page: String,
+    pub name: String,
+    pub revisions: Vec<Revision>,
+    pub site: String,
+    pub title: Option<String>,
+    pub nav: Vec<Nav>,
+}
+
+#[derive(Template)]
+#[template(path = "diff.html")]
+pub struct Diff {
+    pub page: String,
+    pub name: String,
+    pub old: String,
+    pub new: String,
+    pub lines: Vec<DiffLine>,
+    pub site: String,
+    pub title: Option<String>,
+    pub nav: Vec<Nav>,
+}
+
+/// Render `view` of the page at `rest` below the collection's prefix.
+pub fn render(view: View, loaded: &Loaded, rest: &str, site: &Site) -> Response {
+    let location = match loaded.pages.as_ref() {
+        Pages::Blog(posts) => posts
+            .iter()
+            .find(|post| post.metadata.title == rest)
+            .map(|post| post.location.clone()),
+        Pages::Wiki(pages) => pages
+            .iter()
+            .find(|page| page.location() == rest)
+            .map(|page| page.location().to_string()),
+    };
+    // folders and the blog index have no history
+    let Some(location) = location else {
+        return not_found("page not found");
+    };
+
+    let page = format!("{}/{}", loaded.collection.prefix, rest);
+    match view {
+        View::Diff(old, new) => {
+            match git::file_diff(&loaded.collection, &old, &new, Path::new(&location)) {
+                Ok(lines) => Diff {
+                    page,
+                    name: rest.to_string(),
+                    old,
+                    new,
+                    lines,
+                    site: site.host.clone(),
+                    title: site.index.title.clone(),
+                    nav: content::nav(site),
+                }
+                .into_response(),
+                Err(_) => not_found("revisions not found"),
+            }
+        }
+        View::History => match git::file_history(&loaded.collection, Path::new(&location)) {
+            Ok(revisions) => History {
+                page,
+                name: rest.to_string(),
+                revisions,
+                site: site.host.clone(),
+                title: site.index.title.clone(),
+                nav: content::nav(site),
+            }
+            .into_response(),
+            Err(_) => not_found("no history for this page"),
+        },
+        View::Rev(commit) => {
+            let text = match git::file_at(&loaded.collection, &commit, Path::new(&location)) {
+                Ok(text) => text,
+                Err(_) => return not_found("revision not found"),
+            };
+            match loaded.pages.as_ref() {
+                Pages::Blog(_) => {
+                    let (metadata, content) = post::parse(&text);
+                    BlogPost {
+                        content,
+                        metadata,
+                        authorship: None,
+                        site: site.host.clone(),
+                        title: site.index.title.clone(),
+                        nav: content::nav(site),
+                        revision: Some(commit),
+                    }
+                    .into_response()
+                }
+                Pages::Wiki(pages) => {
+                    match wiki::wiki_posts(pages, rest.to_string(), &loaded.collection, site) {
+                        Ok(index) => index
+                            .at_revision(wiki::parse(&location, &text), commit)
+                            .into_response(),
+                        Err(e) => e.into_response(),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn not_found(msg: &str) -> Response {
+    status::ErrorHandler {
+        code: axum::http::StatusCode::NOT_FOUND,
+        msg: msg.to_string(),
+    }
+    .into_response()
+}
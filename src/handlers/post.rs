@@ -1,10 +1,10 @@
-use crate::handlers::wiki::WikiPost;
-use chrono::prelude::{DateTime, NaiveDate};
-use glob::glob;
+use crate::app::git::Authorship;
+use crate::handlers::content::Source;
+use chrono::prelude::NaiveDate;
 use serde::{Deserialize, Serialize};
 use serde_yaml::Value;
 use std::cmp::Ordering;
-use std::io::{Read, Seek, SeekFrom};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::str;
 
@@ -14,13 +14,21 @@ static DATEFORMAT: &str = "M%m-%d-%Y";
 pub struct PostList {
     pub metadata: Metadata, // Metainformation
     pub content: String,    // Body
+    #[serde(skip)]
+    pub authorship: Option<Authorship>,
+    /// Path of the file inside the collection.
+    #[serde(skip)]
+    pub location: String,
+}
+
+// posts with a date that doesn't parse sort as the oldest
+fn date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date, DATEFORMAT).ok()
 }
 
 impl Ord for PostList {
     fn cmp(&self, other: &Self) -> Ordering {
-        let date1 = NaiveDate::parse_from_str(&self.metadata.date, DATEFORMAT).unwrap();
-        let date2 = NaiveDate::parse_from_str(&other.metadata.date, DATEFORMAT).unwrap();
-        date1.cmp(&date2)
+        date(&self.metadata.date).cmp(&date(&other.metadata.date))
     }
 }
 
@@ -32,19 +40,10 @@ impl PartialOrd for PostList {
 
 impl PartialEq for PostList {
     fn eq(&self, other: &Self) -> bool {
-        let date1 = NaiveDate::parse_from_str(&self.metadata.date, DATEFORMAT).unwrap();
-        let date2 = NaiveDate::parse_from_str(&other.metadata.date, DATEFORMAT).unwrap();
-        date1 == date2
+        self.metadata == other.metadata
     }
 }
 
-pub struct ContextState {
-    pub repos: Vec<std::path::PathBuf>,
-    pub posts: Vec<PostList>,
-    pub wiki: Vec<WikiPost>,
-    pub secret: String,
-}
-
 #[derive(Clone, Serialize, Deserialize, Eq, Debug, Default)]
 pub struct Metadata {
     #[serde(default = "default_date")]
@@ -69,45 +68,52 @@ fn default_title() -> String {
 
 impl PartialEq for Metadata {
     fn eq(&self, other: &Self) -> bool {
-        let dateformat = "M%m-%d-%Y";
-        let date1 = DateTime::parse_from_str(&self.date, dateformat).unwrap();
-        let date2 = DateTime::parse_from_str(&other.date, dateformat).unwrap();
-        date1 == date2
+        date(&self.date) == date(&other.date)
     }
 }
 
-fn post(path: PathBuf) -> Result<PostList, serde_yaml::Error> {
-    println!("loading: {}", path.file_stem().unwrap().to_str().unwrap());
-    let mut file_reader = std::fs::File::open(&path).expect("Could not open file");
-    let mut content = String::new();
-
-    let metadata: Metadata = match serde_yaml::Deserializer::from_reader(&file_reader)
-        .take(1)
-        .next()
-    {
-        Some(doc) => {
-            serde_yaml::from_value(Value::deserialize(doc).expect("Could not deserialize metadata"))
-                .unwrap_or_default()
-        }
+/// Split a markdown file into its front matter and body.
+pub fn parse(text: &str) -> (Metadata, String) {
+    let metadata: Metadata = match serde_yaml::Deserializer::from_str(text).next() {
+        Some(document) => match Value::deserialize(document) {
+            Ok(v) => serde_yaml::from_value(v).unwrap_or_default(),
+            Err(_) => Metadata::default(),
+        },
         None => Metadata::default(),
     };
 
-    // Rewind file
-    file_reader.seek(SeekFrom::Start(0)).unwrap();
-    file_reader.read_to_string(&mut content).unwrap();
-
     // only interested in content
     // there is a better solution but meh
-    content = content.split("---").skip(2).collect::<String>();
+    let content = text.split("---").skip(2).collect::<String>();
 
-    Ok(PostList { metadata, content })
+    (metadata, content)
 }
 
-pub fn load(dir: &Path) -> Result<Vec<PostList>, String> {
-    let mut posts: Vec<PostList> = glob(&format!("{}/*.md", dir.to_str().unwrap()))
-        .expect("Failed to read pattern")
-        .filter_map(Result::ok)
-        .map(|fname| post(fname).unwrap_or_default())
+fn post(location: &Path, text: &str, log: &HashMap<PathBuf, Authorship>) -> PostList {
+    println!(
+        "loading: {}",
+        location.file_stem().unwrap().to_str().unwrap()
+    );
+    let (metadata, content) = parse(text);
+
+    PostList {
+        metadata,
+        content,
+        authorship: log.get(location).cloned(),
+        location: location.to_string_lossy().to_string(),
+    }
+}
+
+pub fn load(files: &[Source], log: &HashMap<PathBuf, Authorship>) -> Result<Vec<PostList>, String> {
+    let mut posts: Vec<PostList> = files
+        .iter()
+        .filter_map(|file| match &file.text {
+            Ok(text) => Some(post(&file.location, text, log)),
+            Err(e) => {
+                println!("skipping {}: {}", file.location.display(), e);
+                None
+            }
+        })
         .collect();
 
     // sort
@@ -116,3 +122,41 @@ pub fn load(dir: &Path) -> Result<Vec<PostList>, String> {
 
     Ok(posts)
 }
+
+/// Collect the problems `load` would silently paper over or panic on.
+pub fn check(files: &[Source]) -> Vec<String> {
+    let mut problems = Vec::new();
+    for file in files {
+        let fname = &file.location;
+        let content = match &file.text {
+            Ok(content) => content,
+            Err(e) => {
+                problems.push(format!("{}: {}", fname.display(), e));
+                continue;
+            }
+        };
+
+        let metadata = match serde_yaml::Deserializer::from_str(content).next() {
+            Some(doc) => Value::deserialize(doc).and_then(serde_yaml::from_value::<Metadata>),
+            None => {
+                problems.push(format!("{}: missing front matter", fname.display()));
+                continue;
+            }
+        };
+
+        match metadata {
+            Ok(metadata) => {
+                if NaiveDate::parse_from_str(&metadata.date, DATEFORMAT).is_err() {
+                    problems.push(format!(
+                        "{}: invalid date '{}', expected format {}",
+                        fname.display(),
+                        metadata.date,
+                        DATEFORMAT
+                    ));
+                }
+            }
+            Err(e) => problems.push(format!("{}: invalid front matter: {}", fname.display(), e)),
+        }
+    }
+    problems
+}
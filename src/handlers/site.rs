@@ -0,0 +1,50 @@
+use crate::app::config::{Config, SharedConfig, Site};
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::HOST, request::Parts},
+};
+use std::convert::Infallible;
+use std::sync::Arc;
+
+/// The site picked by the request's `Host`, unknown hosts get the default site.
+pub struct CurrentSite {
+    pub config: Arc<Config>,
+    pub index: usize,
+}
+
+impl CurrentSite {
+    pub fn site(&self) -> &Site {
+        &self.config.sites[self.index]
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CurrentSite
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let config = parts
+            .extensions
+            .get::<SharedConfig>()
+            .expect("SharedConfig extension missing")
+            .load();
+
+        // HTTP/2 carries the host in the uri instead of a header
+        let host = parts
+            .headers
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .or(parts.uri.host())
+            .map(|host| match host.rsplit_once(':') {
+                Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+                _ => host,
+            });
+
+        let index = host.and_then(|host| config.site_for(host)).unwrap_or(0);
+        Ok(CurrentSite { config, index })
+    }
+}
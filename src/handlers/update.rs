@@ -1,112 +1,329 @@
-use crate::app::git::pull_repository;
-use crate::handlers::{
-    post::{load, ContextState},
-    wiki::load as wikiLoad,
-};
-use axum::extract::Request;
+use crate::app::config::{Collection, Site};
+use crate::app::deploys::{DeployLog, Deployment, Trigger};
+use crate::app::git::{self, pull_repository, Pull};
+use crate::app::jobs::Jobs;
+use crate::handlers::content::{ContextState, Loaded, Pages};
+use crate::handlers::site::CurrentSite;
+use crate::handlers::status;
+use crate::handlers::webhook::{Deliveries, Event, Push, Webhook};
+use axum::extract::{Path, Request};
+use axum::response::{IntoResponse, Response};
 use axum::{
-    async_trait,
-    extract::{Extension, FromRequestParts},
-    http::{request::Parts, HeaderMap, StatusCode},
+    extract::Extension,
+    http::{header, HeaderMap, StatusCode},
+    Json,
 };
 use axum_macros::debug_handler;
-use serde::Deserialize;
-use std::sync::{Arc, Mutex};
-
-const X_HUB_SIGNATURE_256: &str = "x-hub-signature-256";
-#[derive(Deserialize, Debug)]
-pub struct GithubSecret(String);
-
-#[async_trait]
-impl<S> FromRequestParts<S> for GithubSecret
-where
-    S: Send + Sync,
-{
-    type Rejection = (StatusCode, &'static str);
-
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        github_secret(&parts.headers).map(Self).ok_or((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Can't determine authentication header",
-        ))
-    }
-}
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+
+/// Largest webhook body read, pushes with thousands of commits stay well below.
+const MAX_BODY: usize = 5 * 1024 * 1024;
+
+/// Pulls the collection and reloads its pages, shared by the webhook and polling.
+/// Meant for the copy `SharedState::update` hands out. New content that doesn't
+/// pass `content::check` is rolled back and the current pages stay.
+pub fn deploy(loaded: &mut Loaded, trigger: Trigger, log: &DeployLog) -> Result<Pull, git2::Error> {
+    let collection = loaded.collection.clone();
+    log.track(&collection, trigger, || {
+        let old = git::head_commit(&collection).ok();
+        // a reclone is checked before it replaces the checkout, see below for the rest
+        let accept = |fresh: &Collection| Pages::load_checked(fresh).map(drop);
+        let pull = match pull_repository(&collection, accept) {
+            Err(e) => {
+                println!(
+                    "Failed to load repo: {}. \n Reason: {}",
+                    &collection.path.to_str().unwrap_or("dunno"),
+                    e.message()
+                );
+                return Err(e);
+            }
+            Ok(pull) => pull,
+        };
 
-fn verify_signature(secret: &str, body: &[u8], github_sig: &str) -> bool {
-    let digest: String = compute_digest(secret, body);
-    digest.eq_ignore_ascii_case(github_sig)
+        match Pages::load_checked(&collection) {
+            Ok(pages) => {
+                println!("Loading new {} pages! ({})", collection.name, pull);
+                loaded.pages = Arc::new(pages);
+                Ok(pull)
+            }
+            Err(why) => {
+                let why = match old.map(|old| (old, git::rollback(&collection, old))) {
+                    Some((old, Ok(()))) => {
+                        format!("rejected new content, still serving {}: {}", old, why)
+                    }
+                    Some((_, Err(e))) => format!(
+                        "rejected new content, could not roll back ({}): {}",
+                        e.message(),
+                        why
+                    ),
+                    None => format!("rejected new content: {}", why),
+                };
+                println!("{}: {}", collection.name, why);
+                Err(git2::Error::from_str(&why))
+            }
+        }
+    })
 }
 
-fn compute_digest(secret: &str, data: &[u8]) -> String {
-    use hmac::Mac;
-    let mut digest = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())
-        .expect("Failed to create hmac");
-    digest.update(data);
-    let digest = digest.finalize();
-    to_hex(&digest.into_bytes())
+/// What a job did to one collection.
+#[derive(Debug, Clone, Serialize)]
+pub struct Deployed {
+    pub collection: String,
+    /// What the pull did, or why the collection was skipped.
+    pub result: String,
+    /// Commit the collection is at afterwards.
+    pub commit: Option<String>,
+    pub error: Option<String>,
 }
 
-fn to_hex(data: &[u8]) -> String {
-    let mut output = String::with_capacity(data.len() * 2);
-    for &byte in data {
-        output.push(std::char::from_digit(byte as u32 >> 4, 16).unwrap());
-        output.push(std::char::from_digit(byte as u32 & 0x0F, 16).unwrap());
+fn failed(loaded: &Loaded, e: git2::Error) -> Deployed {
+    Deployed {
+        collection: loaded.collection.name.clone(),
+        result: "failed".to_string(),
+        commit: git::head_commit(&loaded.collection)
+            .ok()
+            .map(|id| id.to_string()),
+        error: Some(e.message().to_string()),
     }
-    output
 }
 
-fn github_secret(headers: &HeaderMap) -> Option<String> {
-    headers
-        .get(X_HUB_SIGNATURE_256)
-        .and_then(|hv| hv.to_str().unwrap().split('=').nth(1))
-        .and_then(|s| s.trim().parse::<String>().ok())
+/// Deploy the collections of `site` the push is for.
+pub fn deploy_push(
+    next: &mut ContextState,
+    site: usize,
+    push: &Push,
+    log: &DeployLog,
+) -> Vec<Deployed> {
+    let urls = push.urls();
+    let mut deployed = Vec::new();
+    // a site only deploys its own collections
+    for loaded in next
+        .collections
+        .iter_mut()
+        .filter(|loaded| loaded.site == site)
+        .filter(|loaded| git::is_repository(&loaded.collection, &push.full_name, &urls))
+    {
+        let name = loaded.collection.name.clone();
+        let branch = match git::deployed_branch(&loaded.collection) {
+            Ok(branch) => branch,
+            Err(e) => {
+                deployed.push(failed(loaded, e));
+                continue;
+            }
+        };
+        if !push.refs.contains(&format!("refs/heads/{}", branch)) {
+            let refs = push.refs.join(", ");
+            println!("Ignoring push to {} for {}", refs, name);
+            deployed.push(Deployed {
+                collection: name,
+                result: format!("ignored push to {}, deploys {}", refs, branch),
+                commit: None,
+                error: None,
+            });
+            continue;
+        }
+        let result = deploy(loaded, Trigger::Webhook, log);
+        deployed.push(match result {
+            Ok(pull) => Deployed {
+                collection: name,
+                result: pull.to_string(),
+                commit: git::head_commit(&loaded.collection)
+                    .ok()
+                    .map(|id| id.to_string()),
+                error: None,
+            },
+            Err(e) => failed(loaded, e),
+        });
+    }
+    deployed
 }
 
 #[debug_handler]
 pub async fn update(
-    GithubSecret(user_agent): GithubSecret,
-    Extension(context): Extension<Arc<Mutex<ContextState>>>,
+    webhook: Webhook,
+    current: CurrentSite,
+    Extension(jobs): Extension<Jobs>,
+    Extension(deliveries): Extension<Deliveries>,
     request: Request,
-) -> StatusCode {
-    let body = match axum::body::to_bytes(request.into_body(), usize::MAX).await {
+) -> Response {
+    // read before the signature is checked, so keep it to what a push can need
+    let body = match axum::body::to_bytes(request.into_body(), MAX_BODY).await {
         Ok(body) => body,
-        Err(_) => return StatusCode::INTERNAL_SERVER_ERROR,
+        Err(_) => {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("body unreadable or larger than {} bytes", MAX_BODY),
+            )
+                .into_response()
+        }
     };
-    let mut cnt = context.lock().expect("could not lock mutex");
-    let mut verifier = Vec::with_capacity(cnt.repos.len());
-    println!("Got a new pull request...");
-    verifier.push(
-        match verify_signature(&cnt.secret, body.as_ref(), &user_agent) {
-            true => {
-                for (i, repo) in cnt.repos.clone().iter().enumerate() {
-                    match pull_repository(repo) {
-                        Err(e) => {
-                            println!(
-                                "Failed to load repo: {}. \n Reason: {}",
-                                &repo.to_str().unwrap_or("dunno"),
-                                e.message()
-                            );
-                        }
-                        _ => {
-                            if i == 0 {
-                                println!("Loading new blog pages!");
-                                cnt.posts = load(repo).unwrap();
-                            } else {
-                                println!("Loading new wiki pages!");
-                                cnt.wiki = wikiLoad(repo).unwrap();
-                            }
-                        }
-                    };
-                }
-                true
-            }
-            false => false,
-        },
-    );
-
-    if verifier.contains(&true) {
-        StatusCode::OK
-    } else {
-        StatusCode::UNAUTHORIZED
+    // never verify against an empty key
+    let Some(secret) = current.site().content.secret() else {
+        return (StatusCode::UNAUTHORIZED, "no secret configured").into_response();
+    };
+    println!("Got a new pull request from {}...", webhook.forge);
+    if !webhook.verify(secret, body.as_ref()) {
+        return (StatusCode::UNAUTHORIZED, "invalid signature").into_response();
+    }
+    match webhook.event() {
+        Event::Push => (),
+        Event::Ping => return (StatusCode::OK, "pong\n").into_response(),
+        Event::Other(event) => {
+            return (StatusCode::OK, format!("ignored {} event\n", event)).into_response();
+        }
+    }
+    // keyed on the signed body, the delivery header could be left out or changed
+    if !deliveries.first(&body) {
+        println!(
+            "Rejecting replayed delivery {}",
+            webhook.delivery.as_deref().unwrap_or("without id")
+        );
+        return (StatusCode::CONFLICT, "push was already handled").into_response();
+    }
+    let push = match webhook.push(&body) {
+        Ok(push) => push,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("not a push event: {}", e)).into_response()
+        }
+    };
+
+    // the forge only waits a few seconds, deploy after answering
+    let job = jobs.submit(current.index, push);
+    let url = format!("/update/jobs/{}", job.id);
+    (StatusCode::ACCEPTED, [(header::LOCATION, url)], Json(job)).into_response()
+}
+
+// the answer to a request without the webhook secret as bearer token, which
+// the job and deploy endpoints want
+fn unauthorized(site: &Site, headers: &HeaderMap) -> Option<Response> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|hv| hv.to_str().ok())
+        .and_then(|hv| hv.strip_prefix("Bearer "));
+    let authorized = match (site.content.secret(), token) {
+        (Some(secret), Some(token)) => secret.as_bytes().ct_eq(token.trim().as_bytes()).into(),
+        _ => false,
+    };
+    (!authorized).then(|| {
+        (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "send the webhook secret as bearer token",
+        )
+            .into_response()
+    })
+}
+
+/// State of a deploy job of the current site, for whoever knows the webhook secret.
+pub async fn job(
+    Path(id): Path<u64>,
+    current: CurrentSite,
+    headers: HeaderMap,
+    Extension(jobs): Extension<Jobs>,
+) -> Response {
+    if let Some(response) = unauthorized(current.site(), &headers) {
+        return response;
+    }
+    match jobs.get(current.index, id) {
+        Some(job) => Json(job).into_response(),
+        None => status::ErrorHandler {
+            code: StatusCode::NOT_FOUND,
+            msg: "job not found".to_string(),
+        }
+        .into_response(),
+    }
+}
+
+/// Deploy history of the site's collections, for whoever knows the webhook secret.
+pub async fn deploys(
+    current: CurrentSite,
+    headers: HeaderMap,
+    Extension(log): Extension<DeployLog>,
+) -> Response {
+    let site = current.site();
+    if let Some(response) = unauthorized(site, &headers) {
+        return response;
+    }
+
+    let deploys: BTreeMap<String, Vec<Deployment>> = site
+        .content
+        .collections
+        .iter()
+        .filter(|collection| collection.repository.is_some())
+        .map(|collection| (collection.name.clone(), log.get(collection)))
+        .collect();
+    Json(deploys).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::config::Diverge;
+    use crate::app::fixture::{post, Fixture};
+
+    const BAD: &str = "---\ntitle: bad\ndate: garbage\n---\n# bad\n";
+
+    fn checkout(fixture: &Fixture, on_diverge: Diverge) -> (Loaded, DeployLog) {
+        let collection = fixture.collection(on_diverge);
+        git::prepare_checkout(&collection).unwrap();
+        let pages = Arc::new(Pages::load_checked(&collection).unwrap());
+        let loaded = Loaded {
+            site: 0,
+            collection,
+            pages,
+        };
+        (loaded, DeployLog::open(&Fixture::server()))
+    }
+
+    #[test]
+    fn deploys_new_content() {
+        let fixture = Fixture::new();
+        fixture.commit(&[("first.md", &post("first"))]);
+        let (mut loaded, log) = checkout(&fixture, Diverge::FfOnly);
+        let new = fixture.commit(&[("second.md", &post("second"))]);
+
+        let pull = deploy(&mut loaded, Trigger::Webhook, &log).unwrap();
+        assert_eq!(pull, Pull::FastForward);
+        assert_eq!(git::head_commit(&loaded.collection).unwrap(), new);
+        assert_eq!(loaded.pages.len(), 2);
+    }
+
+    #[test]
+    fn rolls_back_rejected_content() {
+        let fixture = Fixture::new();
+        let good = fixture.commit(&[("first.md", &post("first"))]);
+        let (mut loaded, log) = checkout(&fixture, Diverge::FfOnly);
+        let pages = loaded.pages.clone();
+        fixture.commit(&[("bad.md", BAD)]);
+
+        let e = deploy(&mut loaded, Trigger::Webhook, &log).unwrap_err();
+        assert!(e
+            .message()
+            .starts_with("rejected new content, still serving"));
+        assert_eq!(git::head_commit(&loaded.collection).unwrap(), good);
+        assert!(!loaded.collection.path.join("bad.md").exists());
+        assert!(Arc::ptr_eq(&loaded.pages, &pages));
+        assert!(log.get(&loaded.collection)[0].error.is_some());
+    }
+
+    #[test]
+    fn rejects_recloned_content() {
+        let fixture = Fixture::new();
+        let base = fixture.commit(&[("first.md", &post("first"))]);
+        let good = fixture.commit(&[("second.md", &post("second"))]);
+        let (mut loaded, log) = checkout(&fixture, Diverge::Reclone);
+        let pages = loaded.pages.clone();
+        // force-push, the old commit is gone from the remote
+        fixture.commit_on(Some(base), &[("bad.md", BAD)]);
+
+        let e = deploy(&mut loaded, Trigger::Webhook, &log).unwrap_err();
+        assert!(e.message().contains("rejected new content"));
+        assert_eq!(git::head_commit(&loaded.collection).unwrap(), good);
+        assert!(!loaded.collection.path.join("bad.md").exists());
+        assert!(!fixture.dir.join("checkout.reclone").exists());
+        assert!(Arc::ptr_eq(&loaded.pages, &pages));
     }
 }
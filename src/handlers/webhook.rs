@@ -0,0 +1,787 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, HeaderMap, StatusCode},
+};
+use hmac::Mac;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use subtle::ConstantTimeEq;
+
+const X_HUB_SIGNATURE_256: &str = "x-hub-signature-256";
+const X_HUB_SIGNATURE: &str = "x-hub-signature";
+const X_GITLAB_TOKEN: &str = "x-gitlab-token";
+const X_GITEA_SIGNATURE: &str = "x-gitea-signature";
+const X_FORGEJO_SIGNATURE: &str = "x-forgejo-signature";
+// only Bitbucket sends it, both Cloud and Data Center
+const X_EVENT_KEY: &str = "x-event-key";
+
+/// Pushes remembered to reject replays, the oldest are forgotten first.
+const DELIVERIES: usize = 1024;
+
+/// Where a webhook came from, told apart by its headers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Forge {
+    Github,
+    Gitlab,
+    Gitea,
+    Forgejo,
+    Bitbucket,
+}
+
+impl fmt::Display for Forge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Forge::Github => "GitHub",
+            Forge::Gitlab => "GitLab",
+            Forge::Gitea => "Gitea",
+            Forge::Forgejo => "Forgejo",
+            Forge::Bitbucket => "Bitbucket",
+        })
+    }
+}
+
+impl Forge {
+    fn event_header(&self) -> &'static str {
+        match self {
+            Forge::Github => "x-github-event",
+            Forge::Gitlab => "x-gitlab-event",
+            Forge::Gitea => "x-gitea-event",
+            Forge::Forgejo => "x-forgejo-event",
+            Forge::Bitbucket => X_EVENT_KEY,
+        }
+    }
+
+    // Bitbucket Cloud sends `x-request-uuid`, Data Center `x-request-id`
+    fn delivery_headers(&self) -> &'static [&'static str] {
+        match self {
+            Forge::Github => &["x-github-delivery"],
+            Forge::Gitlab => &["x-gitlab-event-uuid"],
+            Forge::Gitea => &["x-gitea-delivery"],
+            Forge::Forgejo => &["x-forgejo-delivery"],
+            Forge::Bitbucket => &["x-request-uuid", "x-request-id"],
+        }
+    }
+
+    /// Events that deploy, everything else is acknowledged and ignored.
+    fn push_events(&self) -> &'static [&'static str] {
+        match self {
+            Forge::Github | Forge::Gitea | Forge::Forgejo => &["push"],
+            Forge::Gitlab => &["Push Hook"],
+            Forge::Bitbucket => &["repo:push", "repo:refs_changed"],
+        }
+    }
+
+    fn ping_events(&self) -> &'static [&'static str] {
+        match self {
+            Forge::Github => &["ping"],
+            Forge::Bitbucket => &["diagnostics:ping"],
+            Forge::Gitlab | Forge::Gitea | Forge::Forgejo => &[],
+        }
+    }
+}
+
+/// What the forge says the webhook is about.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Ping,
+    Push,
+    Other(String),
+}
+
+/// The forge of a webhook and the signature (or GitLab's token) it was sent with.
+#[derive(Debug)]
+pub struct Webhook {
+    pub forge: Forge,
+    signature: Vec<u8>,
+    event: String,
+    /// Id the forge gave this delivery, if it sends one.
+    pub delivery: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Webhook
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        detect(&parts.headers)
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get(name)
+        .and_then(|hv| hv.to_str().ok())
+        .map(str::trim)
+}
+
+const MALFORMED: (StatusCode, &str) = (StatusCode::BAD_REQUEST, "malformed signature header");
+
+// Gitea and Forgejo send `x-hub-signature-256` as well, check their own headers first
+fn detect(headers: &HeaderMap) -> Result<Webhook, (StatusCode, &'static str)> {
+    let hmac = |signature: &str| from_hex(signature).ok_or(MALFORMED);
+    let (forge, signature) = if let Some(token) = header(headers, X_GITLAB_TOKEN) {
+        (Forge::Gitlab, token.as_bytes().to_vec())
+    } else if let Some(signature) = header(headers, X_FORGEJO_SIGNATURE) {
+        (Forge::Forgejo, hmac(signature)?)
+    } else if let Some(signature) = header(headers, X_GITEA_SIGNATURE) {
+        (Forge::Gitea, hmac(signature)?)
+    } else if header(headers, X_EVENT_KEY).is_some() {
+        let signature = header(headers, X_HUB_SIGNATURE)
+            .ok_or((StatusCode::UNAUTHORIZED, "missing X-Hub-Signature header"))?;
+        (
+            Forge::Bitbucket,
+            hmac(signature.strip_prefix("sha256=").ok_or(MALFORMED)?)?,
+        )
+    } else {
+        let signature = header(headers, X_HUB_SIGNATURE_256)
+            .ok_or((StatusCode::UNAUTHORIZED, "missing signature header"))?;
+        (
+            Forge::Github,
+            hmac(signature.strip_prefix("sha256=").ok_or(MALFORMED)?)?,
+        )
+    };
+
+    let event = header(headers, forge.event_header())
+        .ok_or((StatusCode::BAD_REQUEST, "missing event header"))?;
+    let delivery = forge
+        .delivery_headers()
+        .iter()
+        .filter_map(|name| header(headers, name))
+        .find(|delivery| !delivery.is_empty())
+        .map(str::to_string);
+    // these always send one, older GitLab and Bitbucket versions don't
+    if delivery.is_none() && matches!(forge, Forge::Github | Forge::Gitea | Forge::Forgejo) {
+        return Err((StatusCode::BAD_REQUEST, "missing delivery header"));
+    }
+    Ok(Webhook {
+        forge,
+        signature,
+        event: event.to_string(),
+        delivery,
+    })
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    // from_str_radix would take a sign as well
+    if !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+impl Webhook {
+    /// Whether the body was signed with, or for GitLab sent along with, `secret`.
+    /// Takes the same time however much of the signature matches.
+    pub fn verify(&self, secret: &str, body: &[u8]) -> bool {
+        match self.forge {
+            Forge::Gitlab => self.signature.ct_eq(secret.as_bytes()).into(),
+            _ => {
+                let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())
+                    .expect("Failed to create hmac");
+                mac.update(body);
+                mac.verify_slice(&self.signature).is_ok()
+            }
+        }
+    }
+
+    pub fn event(&self) -> Event {
+        if self.forge.push_events().contains(&self.event.as_str()) {
+            Event::Push
+        } else if self.forge.ping_events().contains(&self.event.as_str()) {
+            Event::Ping
+        } else {
+            Event::Other(self.event.clone())
+        }
+    }
+
+    /// Read the repository and branches out of the forge's push event.
+    pub fn push(&self, body: &[u8]) -> Result<Push, serde_json::Error> {
+        Ok(match self.forge {
+            Forge::Github | Forge::Gitea | Forge::Forgejo => {
+                serde_json::from_slice::<GithubPush>(body)?.into()
+            }
+            Forge::Gitlab => serde_json::from_slice::<GitlabPush>(body)?.into(),
+            Forge::Bitbucket => serde_json::from_slice::<BitbucketPush>(body)?.into(),
+        })
+    }
+}
+
+/// SHA-256 of the push bodies already handled, shared by all requests. The
+/// body is what the signature covers, a replay can't change it.
+#[derive(Clone, Default)]
+pub struct Deliveries(Arc<Mutex<VecDeque<[u8; 32]>>>);
+
+impl Deliveries {
+    /// Remember the verified `body`, false if it was seen before.
+    pub fn first(&self, body: &[u8]) -> bool {
+        use sha2::Digest;
+        let digest: [u8; 32] = sha2::Sha256::digest(body).into();
+        let mut seen = self.0.lock().expect("deliveries lock poisoned");
+        if seen.contains(&digest) {
+            return false;
+        }
+        if seen.len() == DELIVERIES {
+            seen.pop_front();
+        }
+        seen.push_back(digest);
+        true
+    }
+}
+
+/// A push, whichever forge it came from.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Push {
+    /// `owner/repo`, or `PROJECT/repo` on Bitbucket Data Center.
+    #[serde(rename = "repository")]
+    pub full_name: String,
+    /// Urls the repository can be cloned or browsed at.
+    #[serde(skip)]
+    pub urls: Vec<String>,
+    /// Updated refs, `refs/heads/<branch>`.
+    pub refs: Vec<String>,
+}
+
+impl Push {
+    pub fn urls(&self) -> Vec<&str> {
+        self.urls.iter().map(String::as_str).collect()
+    }
+}
+
+// GitHub, Gitea and Forgejo share the payload
+#[derive(Deserialize, Debug)]
+struct GithubPush {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    repository: GithubRepository,
+}
+
+#[derive(Deserialize, Debug)]
+struct GithubRepository {
+    full_name: String,
+    clone_url: Option<String>,
+    ssh_url: Option<String>,
+    git_url: Option<String>,
+    html_url: Option<String>,
+}
+
+impl From<GithubPush> for Push {
+    fn from(push: GithubPush) -> Push {
+        let repository = push.repository;
+        Push {
+            full_name: repository.full_name,
+            urls: [
+                repository.clone_url,
+                repository.ssh_url,
+                repository.git_url,
+                repository.html_url,
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            refs: vec![push.git_ref],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct GitlabPush {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    project: GitlabProject,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitlabProject {
+    path_with_namespace: String,
+    git_http_url: Option<String>,
+    git_ssh_url: Option<String>,
+    web_url: Option<String>,
+}
+
+impl From<GitlabPush> for Push {
+    fn from(push: GitlabPush) -> Push {
+        let project = push.project;
+        Push {
+            full_name: project.path_with_namespace,
+            urls: [project.git_http_url, project.git_ssh_url, project.web_url]
+                .into_iter()
+                .flatten()
+                .collect(),
+            refs: vec![push.git_ref],
+        }
+    }
+}
+
+// Cloud sends `push.changes`, Data Center `changes`, a push can update several branches
+#[derive(Deserialize, Debug)]
+struct BitbucketPush {
+    repository: BitbucketRepository,
+    push: Option<BitbucketChanges>,
+    #[serde(default)]
+    changes: Vec<BitbucketServerChange>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketRepository {
+    full_name: Option<String>,
+    slug: Option<String>,
+    project: Option<BitbucketProject>,
+    #[serde(default)]
+    links: BitbucketLinks,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketProject {
+    key: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct BitbucketLinks {
+    html: Option<BitbucketLink>,
+    #[serde(default)]
+    clone: Vec<BitbucketLink>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketLink {
+    href: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketChanges {
+    changes: Vec<BitbucketChange>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketChange {
+    /// Unset when the branch was deleted.
+    new: Option<BitbucketRef>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketRef {
+    #[serde(rename = "type")]
+    kind: String,
+    name: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct BitbucketServerChange {
+    #[serde(rename = "refId")]
+    ref_id: String,
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+impl From<BitbucketPush> for Push {
+    fn from(push: BitbucketPush) -> Push {
+        let repository = push.repository;
+        let full_name = match (repository.full_name, repository.project, repository.slug) {
+            (Some(full_name), _, _) => full_name,
+            (None, Some(BitbucketProject { key: Some(key) }), Some(slug)) => {
+                format!("{}/{}", key, slug)
+            }
+            (None, _, slug) => slug.unwrap_or_default(),
+        };
+
+        let cloud = push
+            .push
+            .into_iter()
+            .flat_map(|push| push.changes)
+            .filter_map(|change| change.new)
+            .filter(|new| new.kind == "branch")
+            .map(|new| format!("refs/heads/{}", new.name));
+        let server = push
+            .changes
+            .into_iter()
+            .filter(|change| change.kind != "DELETE")
+            .map(|change| change.ref_id);
+
+        Push {
+            full_name,
+            urls: repository
+                .links
+                .html
+                .into_iter()
+                .chain(repository.links.clone)
+                .map(|link| link.href)
+                .collect(),
+            refs: cloud.chain(server).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const SECRET: &str = "super-secret-webhook-key";
+    const BODY: &[u8] = br#"{"zen":"Keep it logically awesome."}"#;
+    // HMAC-SHA256 of BODY with SECRET
+    const SIGNATURE: &str = "ec6309a13e9bb4fc05ac26392101051d2a3ad93461173e996742779b66253028";
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(from_hex("00ff7A"), Some(vec![0x00, 0xff, 0x7a]));
+        assert_eq!(from_hex(""), Some(vec![]));
+        assert_eq!(from_hex("abc"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_eq!(from_hex("+1"), None);
+        // two bytes of utf-8, slicing it in the middle would panic
+        assert_eq!(from_hex("é"), None);
+    }
+
+    #[test]
+    fn malformed_signatures() {
+        for signature in ["sha256=abc", "sha256=zz", "sha1=00", "00"] {
+            let headers = headers(&[
+                ("x-hub-signature-256", signature),
+                ("x-github-event", "push"),
+            ]);
+            assert_eq!(detect(&headers).unwrap_err().0, StatusCode::BAD_REQUEST);
+        }
+        let headers = headers(&[("x-github-event", "push")]);
+        assert_eq!(detect(&headers).unwrap_err().0, StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn verify() {
+        let signature = format!("sha256={}", SIGNATURE);
+        let webhook = detect(&headers(&[
+            ("x-hub-signature-256", &signature),
+            ("x-github-event", "ping"),
+            ("x-github-delivery", "72d3162e-cc78-11e3-81ab-4c9367dc0958"),
+        ]))
+        .unwrap();
+        assert_eq!(webhook.forge, Forge::Github);
+        assert_eq!(webhook.event(), Event::Ping);
+        assert!(webhook.verify(SECRET, BODY));
+        assert!(!webhook.verify("wrong", BODY));
+        assert!(!webhook.verify(SECRET, b"{}"));
+
+        let webhook = detect(&headers(&[
+            ("x-gitlab-token", SECRET),
+            ("x-gitlab-event", "Push Hook"),
+        ]))
+        .unwrap();
+        assert!(webhook.verify(SECRET, b"anything"));
+        assert!(!webhook.verify("super-secret", b"anything"));
+    }
+
+    #[test]
+    fn bitbucket_delivery() {
+        let signature = format!("sha256={}", SIGNATURE);
+        let cloud = detect(&headers(&[
+            ("x-hub-signature", &signature),
+            ("x-event-key", "repo:push"),
+            ("x-request-uuid", "c1b6e1c4-8d4c-4a3e-9d0b-1b2b3c4d5e6f"),
+        ]))
+        .unwrap();
+        assert_eq!(cloud.forge, Forge::Bitbucket);
+        assert_eq!(
+            cloud.delivery.as_deref(),
+            Some("c1b6e1c4-8d4c-4a3e-9d0b-1b2b3c4d5e6f")
+        );
+
+        let data_center = detect(&headers(&[
+            ("x-hub-signature", &signature),
+            ("x-event-key", "repo:refs_changed"),
+            ("x-request-id", "6b8d5d8b-7d0e-4c8e-a8a4-2a0b3d5c2f10"),
+        ]))
+        .unwrap();
+        assert_eq!(data_center.event(), Event::Push);
+        assert_eq!(
+            data_center.delivery.as_deref(),
+            Some("6b8d5d8b-7d0e-4c8e-a8a4-2a0b3d5c2f10")
+        );
+    }
+
+    #[test]
+    fn replays() {
+        let deliveries = Deliveries::default();
+        assert!(deliveries.first(b"a"));
+        assert!(deliveries.first(b"b"));
+        assert!(!deliveries.first(b"a"));
+        for i in 0..DELIVERIES {
+            deliveries.first(i.to_string().as_bytes());
+        }
+        // forgotten once enough newer deliveries came in
+        assert!(deliveries.first(b"a"));
+    }
+
+    #[test]
+    fn missing_delivery() {
+        let signature = format!("sha256={}", SIGNATURE);
+        let github = headers(&[
+            ("x-hub-signature-256", &signature),
+            ("x-github-event", "push"),
+        ]);
+        assert_eq!(detect(&github).unwrap_err().0, StatusCode::BAD_REQUEST);
+        let gitea = headers(&[
+            ("x-gitea-signature", SIGNATURE),
+            ("x-gitea-event", "push"),
+            ("x-gitea-delivery", ""),
+        ]);
+        assert_eq!(detect(&gitea).unwrap_err().0, StatusCode::BAD_REQUEST);
+        // GitLab only sends one since 15.x
+        let gitlab = headers(&[("x-gitlab-token", SECRET), ("x-gitlab-event", "Push Hook")]);
+        assert_eq!(detect(&gitlab).unwrap().delivery, None);
+    }
+
+    fn push(forge: Forge, body: &str) -> Push {
+        let webhook = Webhook {
+            forge,
+            signature: Vec::new(),
+            event: forge.push_events()[0].to_string(),
+            delivery: None,
+        };
+        assert_eq!(webhook.event(), Event::Push);
+        webhook.push(body.as_bytes()).unwrap()
+    }
+
+    // shaped like the forges' documented payloads, trimmed to what matters
+    const GITHUB: &str = r#"{
+        "ref": "refs/heads/main",
+        "before": "9049f1265b7d61be4a8904a9a27120d2064dab3b",
+        "after": "0d1a26e67d8f5eaf1f6ba5c57fc3c7d91ac0fd1c",
+        "repository": {
+            "id": 186853002,
+            "name": "blog",
+            "full_name": "b401/blog",
+            "private": false,
+            "html_url": "https://github.com/b401/blog",
+            "git_url": "git://github.com/b401/blog.git",
+            "ssh_url": "git@github.com:b401/blog.git",
+            "clone_url": "https://github.com/b401/blog.git"
+        },
+        "pusher": {"name": "b401", "email": "b401@users.noreply.github.com"},
+        "commits": []
+    }"#;
+
+    const GITEA: &str = r#"{
+        "ref": "refs/heads/published",
+        "before": "28e1879d029cb852e4844d9c718537df08844e03",
+        "after": "bffeb74224043ba2feb48d137756c8a9331c449a",
+        "compare_url": "https://git.example.org/b401/wiki/compare/28e1879d02...bffeb74224",
+        "repository": {
+            "id": 140,
+            "owner": {"login": "b401"},
+            "name": "wiki",
+            "full_name": "b401/wiki",
+            "html_url": "https://git.example.org/b401/wiki",
+            "ssh_url": "git@git.example.org:b401/wiki.git",
+            "clone_url": "https://git.example.org/b401/wiki.git"
+        },
+        "pusher": {"login": "b401"}
+    }"#;
+
+    const GITLAB: &str = r#"{
+        "object_kind": "push",
+        "event_name": "push",
+        "before": "95790bf891e76fee5e1747ab589903a6a1f80f22",
+        "after": "da1560886d4f094c3e6c9ef40349f7d38b5d27d7",
+        "ref": "refs/heads/main",
+        "project_id": 15,
+        "project": {
+            "id": 15,
+            "name": "Notes",
+            "web_url": "https://gitlab.example.com/group/notes",
+            "git_ssh_url": "git@gitlab.example.com:group/notes.git",
+            "git_http_url": "https://gitlab.example.com/group/notes.git",
+            "namespace": "group",
+            "path_with_namespace": "group/notes",
+            "default_branch": "main"
+        },
+        "commits": [],
+        "total_commits_count": 0
+    }"#;
+
+    const BITBUCKET_CLOUD: &str = r#"{
+        "actor": {"display_name": "b401"},
+        "repository": {
+            "type": "repository",
+            "name": "notes",
+            "full_name": "b401/notes",
+            "links": {"html": {"href": "https://bitbucket.org/b401/notes"}}
+        },
+        "push": {
+            "changes": [
+                {
+                    "new": {"type": "branch", "name": "main", "target": {"hash": "709d658dc5b6d6afcd46049c2f332ee3f515a67d"}},
+                    "old": {"type": "branch", "name": "main"}
+                },
+                {
+                    "new": {"type": "tag", "name": "v1.0"},
+                    "old": null
+                },
+                {
+                    "new": null,
+                    "old": {"type": "branch", "name": "old-drafts"}
+                }
+            ]
+        }
+    }"#;
+
+    const BITBUCKET_DATA_CENTER: &str = r#"{
+        "eventKey": "repo:refs_changed",
+        "date": "2017-09-19T09:45:32+1000",
+        "actor": {"name": "admin"},
+        "repository": {
+            "slug": "notes",
+            "id": 84,
+            "name": "notes",
+            "project": {"key": "PROJ", "id": 84, "name": "Project"},
+            "links": {
+                "clone": [
+                    {"href": "ssh://git@bitbucket.example.com:7999/proj/notes.git", "name": "ssh"},
+                    {"href": "https://bitbucket.example.com/scm/proj/notes.git", "name": "http"}
+                ],
+                "self": [{"href": "https://bitbucket.example.com/projects/PROJ/repos/notes/browse"}]
+            }
+        },
+        "changes": [
+            {
+                "ref": {"id": "refs/heads/main", "displayId": "main", "type": "BRANCH"},
+                "refId": "refs/heads/main",
+                "fromHash": "ecddabb624f6f5ba43816f5926e580a5f680a932",
+                "toHash": "178864a7d521b6f5e720b386b2c2b0ef8563e0dc",
+                "type": "UPDATE"
+            },
+            {
+                "ref": {"id": "refs/heads/gone", "displayId": "gone", "type": "BRANCH"},
+                "refId": "refs/heads/gone",
+                "fromHash": "ecddabb624f6f5ba43816f5926e580a5f680a932",
+                "toHash": "0000000000000000000000000000000000000000",
+                "type": "DELETE"
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn github_push() {
+        let push = push(Forge::Github, GITHUB);
+        assert_eq!(push.full_name, "b401/blog");
+        assert_eq!(push.refs, ["refs/heads/main"]);
+        assert_eq!(
+            push.urls(),
+            [
+                "https://github.com/b401/blog.git",
+                "git@github.com:b401/blog.git",
+                "git://github.com/b401/blog.git",
+                "https://github.com/b401/blog",
+            ]
+        );
+    }
+
+    #[test]
+    fn gitea_push() {
+        for forge in [Forge::Gitea, Forge::Forgejo] {
+            let push = push(forge, GITEA);
+            assert_eq!(push.full_name, "b401/wiki");
+            assert_eq!(push.refs, ["refs/heads/published"]);
+            assert!(push
+                .urls()
+                .contains(&"https://git.example.org/b401/wiki.git"));
+        }
+    }
+
+    #[test]
+    fn gitlab_push() {
+        let push = push(Forge::Gitlab, GITLAB);
+        assert_eq!(push.full_name, "group/notes");
+        assert_eq!(push.refs, ["refs/heads/main"]);
+        assert_eq!(
+            push.urls(),
+            [
+                "https://gitlab.example.com/group/notes.git",
+                "git@gitlab.example.com:group/notes.git",
+                "https://gitlab.example.com/group/notes",
+            ]
+        );
+    }
+
+    #[test]
+    fn bitbucket_cloud_push() {
+        let push = push(Forge::Bitbucket, BITBUCKET_CLOUD);
+        assert_eq!(push.full_name, "b401/notes");
+        // tags and deleted branches deploy nothing
+        assert_eq!(push.refs, ["refs/heads/main"]);
+        assert_eq!(push.urls(), ["https://bitbucket.org/b401/notes"]);
+    }
+
+    #[test]
+    fn bitbucket_data_center_push() {
+        let push = push(Forge::Bitbucket, BITBUCKET_DATA_CENTER);
+        assert_eq!(push.full_name, "PROJ/notes");
+        assert_eq!(push.refs, ["refs/heads/main"]);
+        assert_eq!(
+            push.urls(),
+            [
+                "ssh://git@bitbucket.example.com:7999/proj/notes.git",
+                "https://bitbucket.example.com/scm/proj/notes.git",
+            ]
+        );
+    }
+
+    #[test]
+    fn detect_forge() {
+        let signature = format!("sha256={}", SIGNATURE);
+        for (pairs, forge) in [
+            (
+                vec![
+                    ("x-gitea-signature", SIGNATURE),
+                    ("x-hub-signature-256", signature.as_str()),
+                    ("x-gitea-event", "push"),
+                    ("x-gitea-delivery", "1"),
+                ],
+                Forge::Gitea,
+            ),
+            (
+                vec![
+                    ("x-forgejo-signature", SIGNATURE),
+                    ("x-gitea-signature", SIGNATURE),
+                    ("x-forgejo-event", "push"),
+                    ("x-forgejo-delivery", "2"),
+                ],
+                Forge::Forgejo,
+            ),
+            (
+                vec![("x-gitlab-token", SECRET), ("x-gitlab-event", "Push Hook")],
+                Forge::Gitlab,
+            ),
+            (
+                vec![
+                    ("x-hub-signature", signature.as_str()),
+                    ("x-event-key", "repo:push"),
+                ],
+                Forge::Bitbucket,
+            ),
+        ] {
+            let webhook = detect(&headers(&pairs)).unwrap();
+            assert_eq!(webhook.forge, forge);
+            assert_eq!(webhook.event(), Event::Push);
+        }
+
+        // a forge's own event header is required
+        let headers = headers(&[("x-gitea-signature", SIGNATURE), ("x-github-event", "push")]);
+        assert_eq!(detect(&headers).unwrap_err().0, StatusCode::BAD_REQUEST);
+    }
+}
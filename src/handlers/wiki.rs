@@ -1,28 +1,25 @@
 use crate::{
-    app,
+    app::{
+        config::{Collection, Site},
+        git::Authorship,
+    },
     handlers::{
-        post::{ContextState, Metadata},
+        content::{self, Nav, Source},
+        post::{self, Metadata},
         status,
     },
 };
 use askama_axum::Template;
-use axum::extract::{Extension, Path};
 use axum::http::StatusCode;
-use axum_macros::debug_handler;
-use glob::glob;
-use serde::Deserialize;
-use serde_yaml::Value;
-use std::io::{Read, Seek, SeekFrom};
-use std::path::Path as pathPath;
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use std::path::{Path as pathPath, PathBuf};
 
 #[derive(Debug, Default, Clone)]
 pub struct WikiPost {
     location: String,
-    #[allow(dead_code)]
-    // TODO: Will be used later to include author + date
     metadata: Metadata,
     content: String,
+    authorship: Option<Authorship>,
 }
 
 #[derive(Template, Debug, Default, Clone)]
@@ -32,22 +29,35 @@ pub struct WikiIndex {
     documents: Vec<String>,
     current: String,
     folders: Vec<String>,
+    prefix: String,
     site: String,
     title: Option<String>,
+    nav: Vec<Nav>,
+    /// Commit the shown page is taken from, the deployed one if unset.
+    revision: Option<String>,
 }
 
-#[debug_handler]
-pub async fn wiki_posts(
-    path: Option<Path<String>>,
-    Extension(posts): Extension<Arc<Mutex<ContextState>>>,
-    Extension(site): Extension<String>,
-    Extension(index): Extension<app::config::IndexPage>,
+impl WikiPost {
+    pub fn location(&self) -> &str {
+        &self.location
+    }
+}
+
+impl WikiIndex {
+    /// Show `content` from an older commit in place of the deployed page.
+    pub fn at_revision(mut self, content: WikiPost, commit: String) -> WikiIndex {
+        self.content = Some(content);
+        self.revision = Some(commit);
+        self
+    }
+}
+
+pub fn wiki_posts(
+    wiki_posts: &[WikiPost],
+    current: String,
+    collection: &Collection,
+    site: &Site,
 ) -> Result<WikiIndex, status::ErrorHandler> {
-    let wiki_posts = posts.lock().unwrap().wiki.clone();
-    let current = match path {
-        Some(path) => path.to_string(),
-        None => "".to_string(),
-    };
     let filtered: Vec<WikiPost> = wiki_posts
         .iter()
         .filter(|a| pathPath::new(&a.location).starts_with(&current))
@@ -103,58 +113,59 @@ pub async fn wiki_posts(
         documents,
         current,
         folders,
-        site: site.to_string(),
-        title: index.title,
+        prefix: collection.prefix.clone(),
+        site: site.host.clone(),
+        title: site.index.title.clone(),
+        nav: content::nav(site),
+        revision: None,
     })
 }
 
-fn post(path: String, dir: String) -> Result<WikiPost, serde_yaml::Error> {
-    let new_path = pathPath::new(&path);
+fn post(location: &pathPath, text: &str, log: &HashMap<PathBuf, Authorship>) -> WikiPost {
     println!(
         "loading wiki: {}",
-        new_path.file_stem().unwrap().to_str().unwrap()
+        location.file_stem().unwrap().to_str().unwrap()
     );
-    let mut file_reader = std::fs::File::open(new_path).expect("Could not open file");
-    let mut content = String::new();
-
-    let metadata: Metadata = match serde_yaml::Deserializer::from_reader(&file_reader)
-        .take(1)
-        .next()
-    {
-        Some(document) => match Value::deserialize(document) {
-            Ok(v) => serde_yaml::from_value(v).unwrap_or_default(),
-            Err(_) => Metadata::default(),
-        },
-        None => Metadata::default(),
-    };
-
-    // Rewind file
-    file_reader.seek(SeekFrom::Start(0)).unwrap();
-    file_reader.read_to_string(&mut content).unwrap();
-
-    // only interested in content
-    // there is a better solution but meh
-    content = content.split("---").skip(2).collect::<String>();
-
-    Ok(WikiPost {
-        location: new_path
-            .strip_prefix(dir)
-            .unwrap()
-            .to_string_lossy()
-            .to_string(),
+    let mut post = parse(&location.to_string_lossy(), text);
+    post.authorship = log.get(location).cloned();
+    post
+}
+
+/// Page at `location` with the markdown in `text`, for files and old revisions alike.
+pub fn parse(location: &str, text: &str) -> WikiPost {
+    let (metadata, content) = post::parse(text);
+    WikiPost {
+        location: location.to_string(),
         metadata,
         content,
-    })
+        authorship: None,
+    }
 }
 
-pub fn load(dir: &pathPath) -> Option<Vec<WikiPost>> {
-    println!("Reading wiki from {:#?}", &dir);
-    let newdir = dir.to_string_lossy().to_string();
-    let posts: Vec<WikiPost> = glob(&format!("{}/**/*.md", dir.to_str().unwrap()))
-        .expect("Failed to read pattern")
-        .filter_map(Result::ok)
-        .map(|name| post(name.to_string_lossy().to_string(), newdir.clone()).unwrap_or_default())
+pub fn load(files: &[Source], log: &HashMap<PathBuf, Authorship>) -> Option<Vec<WikiPost>> {
+    let posts: Vec<WikiPost> = files
+        .iter()
+        .filter_map(|file| match &file.text {
+            Ok(text) => Some(post(&file.location, text, log)),
+            Err(e) => {
+                println!("skipping {}: {}", file.location.display(), e);
+                None
+            }
+        })
         .collect();
 
     Some(posts)
 }
+
+/// Collect the files `load` would skip.
+pub fn check(files: &[Source]) -> Vec<String> {
+    files
+        .iter()
+        .filter_map(|file| {
+            file.text
+                .as_ref()
+                .err()
+                .map(|e| format!("{}: {}", file.location.display(), e))
+        })
+        .collect()
+}
@@ -1,26 +1,46 @@
+use app::cli::{Cli, Command};
+use app::config::SharedConfig;
 use askama_axum::Template;
 use axum::handler::HandlerWithoutStateExt;
 use axum_macros::debug_handler;
+use clap::Parser;
+use handlers::site::CurrentSite;
 use handlers::status;
-use std::process::exit;
+use std::path::PathBuf;
+use std::process::ExitCode;
 mod app {
+    pub mod auth;
+    pub mod cli;
     pub mod config;
+    pub mod deploys;
+    pub mod env;
+    #[cfg(test)]
+    pub mod fixture;
     pub mod git;
+    pub mod jobs;
+    pub mod poll;
+    pub mod reload;
+    pub mod signature;
+    pub mod validate;
+    pub mod watch;
 }
 mod handlers {
     pub mod blog;
+    pub mod content;
+    pub mod history;
     pub mod post;
     pub mod security;
+    pub mod site;
     pub mod status;
     pub mod update;
+    pub mod webhook;
     pub mod wiki;
 }
 use axum::{
     extract::Extension,
-    routing::{get, get_service, post},
+    routing::{get, post},
     Router,
 };
-use std::sync::{Arc, Mutex};
 use tower_http::services::ServeDir;
 
 #[derive(Template)]
@@ -34,23 +54,22 @@ struct Index {
     mail: Option<String>,
     matrix: Option<String>,
     threema: Option<String>,
+    nav: Vec<handlers::content::Nav>,
 }
 
 #[debug_handler]
-async fn index(
-    Extension(site): Extension<String>,
-    Extension(contact): Extension<app::config::Contact>,
-    Extension(index): Extension<app::config::IndexPage>,
-) -> Index {
+async fn index(current: CurrentSite) -> Index {
+    let site = current.site();
     Index {
-        site,
-        slogan: index.slogan,
-        title: index.title,
-        skills: index.skills,
-        links: index.links,
-        mail: contact.mail,
-        matrix: contact.matrix,
-        threema: contact.threema,
+        site: site.host.clone(),
+        slogan: site.index.slogan.clone(),
+        title: site.index.title.clone(),
+        skills: site.index.skills.clone(),
+        links: site.index.links.clone(),
+        mail: site.contact.mail.clone(),
+        matrix: site.contact.matrix.clone(),
+        threema: site.contact.threema.clone(),
+        nav: handlers::content::nav(site),
     }
 }
 
@@ -62,120 +81,178 @@ struct Contact {
     threema: String,
     site: String,
     title: Option<String>,
+    nav: Vec<handlers::content::Nav>,
 }
 
-async fn contact(
-    Extension(details): Extension<app::config::Contact>,
-    Extension(site): Extension<String>,
-    Extension(index): Extension<app::config::IndexPage>,
-) -> Contact {
+async fn contact(current: CurrentSite) -> Contact {
+    let site = current.site();
     Contact {
-        mail: details.mail.unwrap_or_default(),
-        matrix: details.matrix.unwrap_or_default(),
-        threema: details.threema.unwrap_or_default(),
-        site,
-        title: index.title,
+        mail: site.contact.mail.clone().unwrap_or_default(),
+        matrix: site.contact.matrix.clone().unwrap_or_default(),
+        threema: site.contact.threema.clone().unwrap_or_default(),
+        site: site.host.clone(),
+        title: site.index.title.clone(),
+        nav: handlers::content::nav(site),
     }
 }
 
 #[tokio::main]
-async fn main() {
-    let yaml_settings = "/etc/artemis/config.yml".to_string();
-    let settings = match app::config::Config::from_file(yaml_settings) {
-        Ok(setting) => setting,
-        Err(e) => panic!("Could not load config file: {}", e),
-    };
+async fn main() -> ExitCode {
+    match Cli::parse().command {
+        Command::Serve { config } => serve(config).await,
+        Command::Check { config } => check(config),
+        Command::Build { config } => build(config),
+    }
+}
 
-    // clone wiki repo to destination
-    match app::git::clone_repository(
-        &settings.content.wiki.repository,
-        &settings.content.wiki.path,
-    ) {
-        Ok(_) => println!("Cloned repository"),
+fn load_config(path: &std::path::Path) -> Option<app::config::Config> {
+    match app::config::Config::from_file(path) {
+        Ok(setting) => Some(setting),
         Err(e) => {
-            if e.code() != git2::ErrorCode::Exists {
-                println!("{}", e.message());
-                exit(1);
-            }
+            println!("Could not load config file: {}", e);
+            None
+        }
+    }
+}
+
+// clone every content repository, existing checkouts are verified against the config
+fn clone_content(
+    settings: &app::config::Config,
+    log: &app::deploys::DeployLog,
+) -> Result<(), git2::Error> {
+    use app::git::Checkout;
+    for collection in settings.collections() {
+        if collection.repository.is_none() {
+            continue;
         }
+        let checkout = log.track(collection, app::deploys::Trigger::Startup, || {
+            app::git::prepare_checkout(collection)
+        })?;
+        match checkout {
+            Checkout::Cloned => println!("Cloned repository"),
+            Checkout::Verified => (),
+            Checkout::Repaired(why) => println!("{}: repaired checkout, {}", collection.name, why),
+            Checkout::Recloned(why) => println!("{}: cloned again, {}", collection.name, why),
+        }
+    }
+    Ok(())
+}
+
+fn check(config: PathBuf) -> ExitCode {
+    let Some(settings) = load_config(&config) else {
+        return ExitCode::FAILURE;
     };
 
-    // clone blog repo to destination
-    match app::git::clone_repository(
-        &settings.content.blog.repository,
-        &settings.content.blog.path,
-    ) {
-        Ok(_) => println!("Cloned repository"),
-        Err(e) => {
-            if e.code() != git2::ErrorCode::Exists {
-                println!("{}", e.message());
-                exit(1);
+    let mut problems = Vec::new();
+    for collection in settings.collections() {
+        if !collection.path.is_dir() && collection.repository.is_some() {
+            // a fresh machine clones on first start, there is nothing to check yet
+            println!(
+                "{}: not cloned yet, content not checked",
+                collection.path.display()
+            );
+            continue;
+        }
+        if !collection.path.is_dir() {
+            problems.push(format!(
+                "{}: content directory does not exist",
+                collection.path.display()
+            ));
+        }
+        problems.extend(
+            handlers::content::check(collection)
+                .into_iter()
+                .map(|problem| format!("{}/{}", collection.path.display(), problem)),
+        );
+    }
+
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("{}: ok", config.display());
+        ExitCode::SUCCESS
+    } else {
+        println!("{} problem(s) found", problems.len());
+        ExitCode::FAILURE
+    }
+}
+
+fn build(config: PathBuf) -> ExitCode {
+    let Some(settings) = load_config(&config) else {
+        return ExitCode::FAILURE;
+    };
+
+    let log = app::deploys::DeployLog::open(&settings.server);
+    if let Err(e) = clone_content(&settings, &log) {
+        println!("{}", e.message());
+        return ExitCode::FAILURE;
+    }
+
+    let mut failed = false;
+    for collection in settings.collections() {
+        match app::git::pull_repository(collection, |_| Ok(())) {
+            // local collections are used as they are
+            Err(_) if collection.repository.is_none() => (),
+            Ok(pull) => println!("Updated {}: {}", collection.name, pull),
+            Err(e) => {
+                println!(
+                    "Failed to update {}: {}",
+                    collection.path.display(),
+                    e.message()
+                );
+                failed = true;
             }
         }
+        let pages = handlers::content::Pages::load(collection);
+        println!("Loaded {} pages for {}", pages.len(), collection.name);
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+async fn serve(path: PathBuf) -> ExitCode {
+    let Some(settings) = load_config(&path) else {
+        return ExitCode::FAILURE;
     };
 
-    // ugly solution needs to be refined later
-    let image_blog_path = settings
-        .content
-        .blog
-        .path
-        .as_os_str()
-        .to_str()
-        .unwrap()
-        .to_string();
-    let image_wiki_path = settings
-        .content
-        .wiki
-        .path
-        .as_os_str()
-        .to_str()
-        .unwrap()
-        .to_string();
+    let log = app::deploys::DeployLog::open(&settings.server);
+    if let Err(e) = clone_content(&settings, &log) {
+        println!("{}", e.message());
+        return ExitCode::FAILURE;
+    }
 
     // load initial post list
-    let context_state = Arc::new(Mutex::new(handlers::post::ContextState {
-        posts: handlers::post::load(&settings.content.blog.path).unwrap(),
-        wiki: handlers::wiki::load(&settings.content.wiki.path).unwrap(),
-        // Always set the blog path as first element and the wiki path as second element.
-        repos: vec![settings.content.blog.path, settings.content.wiki.path],
-        secret: settings.content.secret.expect("No github secret found."),
-    }));
+    let context_state =
+        handlers::content::SharedState::new(handlers::content::ContextState::load(&settings));
+    app::poll::spawn(context_state.clone(), log.clone());
+    app::watch::spawn(context_state.clone());
+    let jobs = app::jobs::spawn(context_state.clone(), log.clone());
+
+    let listen = format!("{}:{}", settings.server.listen, settings.server.port);
+    let config = SharedConfig::new(settings);
+    app::reload::spawn(path, config.clone());
 
     let middleware = tower::ServiceBuilder::new()
         .layer(Extension(context_state))
-        .layer(Extension(settings.contact))
-        .layer(Extension(settings.server.host))
-        .layer(Extension(settings.index));
+        .layer(Extension(jobs))
+        .layer(Extension(log))
+        .layer(Extension(handlers::webhook::Deliveries::default()))
+        .layer(Extension(config));
 
     let app = Router::new()
         .route("/", get(index))
         .route("/pgp-key.txt", get(handlers::security::pgp_key))
         .route("/.well-known/:file", get(handlers::security::well_known))
-        .route("/blog", get(handlers::blog::blog))
-        // we should fix this through middleware
-        .route("/blog/", get(handlers::blog::blog))
-        .route("/blog/:title", get(handlers::blog::blog_post))
-        .route("/wiki", get(handlers::wiki::wiki_posts))
-        // we should fix this through middleware
-        .route("/wiki/", get(handlers::wiki::wiki_posts))
-        .route("/wiki/*title", get(handlers::wiki::wiki_posts))
         .route("/contact", get(contact))
         .route("/healthz", get(|| async { "health" }))
         .route("/update", post(handlers::update::update))
-        .nest_service(
-            "/b/images",
-            get_service(
-                ServeDir::new(format!("{}/images", image_blog_path))
-                    .not_found_service(status::internal_error().into_service()),
-            ),
-        )
-        .nest_service(
-            "/w/images",
-            get_service(
-                ServeDir::new(format!("{}/images", image_wiki_path))
-                    .not_found_service(status::internal_error().into_service()),
-            ),
-        )
+        .route("/update/jobs/:id", get(handlers::update::job))
+        .route("/update/deploys", get(handlers::update::deploys))
         .nest_service(
             "/static",
             ServeDir::new("./static").not_found_service(status::internal_error().into_service()),
@@ -184,14 +261,15 @@ async fn main() {
             "/css",
             ServeDir::new("./css").not_found_service(status::internal_error().into_service()),
         )
-        // global 404 fallback
-        .fallback(handlers::status::code_404)
+        // collections live below their prefix, anything else is a 404
+        .fallback(handlers::content::dispatch)
         .layer(middleware);
 
-    let listen = &format!("{}:{}", settings.server.listen, settings.server.port);
     println!("A R T E M I S\nlistening on : {}", &listen);
     axum_server::bind(listen.parse().unwrap())
         .serve(app.into_make_service())
         .await
         .unwrap();
+
+    ExitCode::SUCCESS
 }